@@ -0,0 +1,161 @@
+#![no_main]
+
+//! Feeds arbitrary instruction data and account lists to the processor. The
+//! accounts are laid out the way the runtime serializes program input, so
+//! reallocs and duplicate accounts behave as they do on chain. Errors are
+//! expected, panics are not.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use marketplace::{
+    find_config_address, find_pool_address, find_reward_vault_address, find_stake_address,
+    find_vault_address, process_instruction,
+};
+use solana_program::{
+    entrypoint::{deserialize, BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+const PROGRAM_ID: Pubkey = Pubkey::new_from_array([0x4d; 32]);
+
+#[derive(Arbitrary, Debug)]
+enum Key {
+    Program,
+    System,
+    Token,
+    AssociatedToken,
+    Rent,
+    Config,
+    Pool(u8),
+    Vault(u8),
+    RewardVault(u8),
+    Stake(u8, u8),
+    User(u8),
+}
+
+impl Key {
+    fn pubkey(&self) -> Pubkey {
+        let pool = |pool_id: u8| find_pool_address(&PROGRAM_ID, pool_id as u64).0;
+        match *self {
+            Key::Program => PROGRAM_ID,
+            Key::System => system_program::id(),
+            Key::Token => spl_token::id(),
+            Key::AssociatedToken => spl_associated_token_account::id(),
+            Key::Rent => sysvar::rent::id(),
+            Key::Config => find_config_address(&PROGRAM_ID).0,
+            Key::Pool(pool_id) => pool(pool_id),
+            Key::Vault(pool_id) => find_vault_address(&PROGRAM_ID, &pool(pool_id)).0,
+            Key::RewardVault(pool_id) => find_reward_vault_address(&PROGRAM_ID, &pool(pool_id)).0,
+            Key::Stake(pool_id, user) => {
+                find_stake_address(&PROGRAM_ID, &pool(pool_id), &user_key(user)).0
+            }
+            Key::User(user) => user_key(user),
+        }
+    }
+}
+
+fn user_key(user: u8) -> Pubkey {
+    Pubkey::new_from_array([user; 32])
+}
+
+#[derive(Arbitrary, Debug)]
+enum Owner {
+    Program,
+    System,
+    Token,
+}
+
+impl Owner {
+    fn pubkey(&self) -> Pubkey {
+        match self {
+            Owner::Program => PROGRAM_ID,
+            Owner::System => system_program::id(),
+            Owner::Token => spl_token::id(),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Account {
+    Unique {
+        key: Key,
+        owner: Owner,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        is_writable: bool,
+    },
+    // index into the accounts before it, as the runtime dedups them
+    Duplicate(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    accounts: Vec<Account>,
+    instruction_data: Vec<u8>,
+}
+
+fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+// serializes the input in the aligned loader format read by `deserialize`
+fn serialize(input: &Input) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    push_u64(&mut buffer, input.accounts.len() as u64);
+    for (index, account) in input.accounts.iter().enumerate() {
+        match account {
+            Account::Duplicate(original) => {
+                buffer.push((*original as usize % index) as u8);
+                buffer.extend_from_slice(&[0; 7]);
+            }
+            Account::Unique {
+                key,
+                owner,
+                lamports,
+                data,
+                is_signer,
+                is_writable,
+            } => {
+                buffer.push(NON_DUP_MARKER);
+                buffer.push(*is_signer as u8);
+                buffer.push(*is_writable as u8);
+                buffer.push(0); // executable
+                buffer.extend_from_slice(&[0; 4]); // original data length
+                buffer.extend_from_slice(&key.pubkey().to_bytes());
+                buffer.extend_from_slice(&owner.pubkey().to_bytes());
+                push_u64(&mut buffer, *lamports);
+                push_u64(&mut buffer, data.len() as u64);
+                buffer.extend_from_slice(data);
+                buffer.resize(buffer.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+                let padding =
+                    (BPF_ALIGN_OF_U128 - buffer.len() % BPF_ALIGN_OF_U128) % BPF_ALIGN_OF_U128;
+                buffer.resize(buffer.len() + padding, 0);
+                push_u64(&mut buffer, 0); // rent epoch
+            }
+        }
+    }
+    push_u64(&mut buffer, input.instruction_data.len() as u64);
+    buffer.extend_from_slice(&input.instruction_data);
+    buffer.extend_from_slice(&PROGRAM_ID.to_bytes());
+    buffer
+}
+
+fuzz_target!(|input: Input| {
+    // the first account has nothing to duplicate
+    if input.accounts.len() > 16
+        || matches!(input.accounts.first(), Some(Account::Duplicate(_)))
+    {
+        return;
+    }
+    let bytes = serialize(&input);
+
+    // u64 backing keeps the buffer aligned like the program input region
+    let mut aligned = vec![0u64; (bytes.len() + 7) / 8];
+    let buffer = aligned.as_mut_ptr() as *mut u8;
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len()) };
+
+    let (program_id, accounts, instruction_data) = unsafe { deserialize(buffer) };
+    let _ = process_instruction(program_id, &accounts, instruction_data);
+});
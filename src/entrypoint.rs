@@ -0,0 +1,24 @@
+//! Program entrypoint
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+use crate::{error::MarketplaceError, process_instruction};
+
+// Declare and export the program's entrypoint
+entrypoint!(program_entrypoint);
+
+fn program_entrypoint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    if let Err(error) = process_instruction(program_id, accounts, instruction_data) {
+        // log the error message so clients and indexers can see the reason
+        error.print::<MarketplaceError>();
+        return Err(error);
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+//! Error types
+
+use num_derive::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+/// Errors that may be returned by the Marketplace program.
+///
+/// Codes are the variant index and are part of the program's public
+/// interface: new variants are only ever appended.
+#[derive(Clone, Debug, Eq, Error, FromPrimitive, PartialEq)]
+pub enum MarketplaceError {
+    // 0
+    /// A required signature is missing.
+    #[error("Missing required signature")]
+    MissingSignature,
+    /// The signer is not the program admin.
+    #[error("Signer is not the admin")]
+    Unauthorized,
+    /// The config account does not match the config PDA.
+    #[error("Wrong config account")]
+    InvalidConfigAccount,
+    /// The config account has not been initialized.
+    #[error("Config account not initialized")]
+    ConfigNotInitialized,
+    /// The config account has already been initialized.
+    #[error("Config account already initialized")]
+    ConfigAlreadyInitialized,
+
+    // 5
+    /// The config account data could not be deserialized.
+    #[error("Invalid config account data")]
+    InvalidConfigData,
+    /// The pool account does not match the pool PDA.
+    #[error("Wrong pool account")]
+    InvalidPoolAccount,
+    /// The pool account has not been created.
+    #[error("Pool account not initialized")]
+    PoolNotInitialized,
+    /// The pool account has already been created.
+    #[error("Pool account already created")]
+    PoolAlreadyCreated,
+    /// The pool account data could not be deserialized.
+    #[error("Invalid pool account data")]
+    InvalidPoolData,
+
+    // 10
+    /// The vault account does not match the vault PDA.
+    #[error("Wrong vault account")]
+    InvalidVaultAccount,
+    /// The vault account has already been generated.
+    #[error("Vault account already generated")]
+    VaultAlreadyGenerated,
+    /// The stake account does not match the stake PDA.
+    #[error("Wrong stake account")]
+    InvalidStakeAccount,
+    /// The stake account has not been initialized.
+    #[error("Stake account not initialized")]
+    StakeAccountNotInitialized,
+    /// The stake account data could not be deserialized.
+    #[error("Invalid stake account data")]
+    InvalidStakeData,
+
+    // 15
+    /// The stake account belongs to a different staker.
+    #[error("Stake account owner mismatch")]
+    StakerMismatch,
+    /// The mint is not the pool's stake token mint.
+    #[error("Wrong stake token mint")]
+    InvalidStakeMint,
+    /// The mint is not the pool's reward token mint.
+    #[error("Wrong reward token mint")]
+    InvalidRewardMint,
+    /// The vault token account is not the vault's associated token account.
+    #[error("Wrong vault token account")]
+    InvalidVaultTokenAccount,
+    /// The vault token account has not been created.
+    #[error("Vault token account not initialized")]
+    VaultTokenAccountNotInitialized,
+
+    // 20
+    /// The user token account is not the staker's associated token account.
+    #[error("Wrong user token account")]
+    InvalidUserTokenAccount,
+    /// The withdraw amount exceeds the staked amount.
+    #[error("Insufficient staked amount")]
+    InsufficientStake,
+    /// The reward vault holds less than the reward being claimed.
+    #[error("Reward vault is underfunded")]
+    RewardVaultUnderfunded,
+    /// An arithmetic operation overflowed.
+    #[error("Math overflow")]
+    MathOverflow,
+    /// The lock tier is not configured on the pool.
+    #[error("Invalid lock tier")]
+    InvalidLockTier,
+
+    // 25
+    /// The lock tiers or early withdraw penalty are out of range.
+    #[error("Invalid lock tier configuration")]
+    InvalidLockTierConfig,
+    /// The staked principal is locked until the lock end.
+    #[error("Stake is locked")]
+    StakeLocked,
+    /// The pool has an unstake cooldown, so withdrawals go through the queue.
+    #[error("Unstake cooldown required")]
+    UnstakeCooldownRequired,
+    /// The stake account has no pending unstake.
+    #[error("No pending unstake")]
+    NoPendingUnstake,
+
+    // 30
+    /// The unstake cooldown has not elapsed yet.
+    #[error("Unstake cooldown not elapsed")]
+    CooldownNotElapsed,
+    /// The unstake cooldown is negative.
+    #[error("Invalid unstake cooldown")]
+    InvalidUnstakeCooldown,
+    /// The stake account still holds principal, pending unstake or rewards.
+    #[error("Stake account not empty")]
+    StakeAccountNotEmpty,
+    /// The stake account already uses the current layout.
+    #[error("Stake account already migrated")]
+    StakeAccountAlreadyMigrated,
+    /// The account data does not start with the expected type discriminator.
+    #[error("Invalid account discriminator")]
+    InvalidAccountDiscriminator,
+
+    // 35
+    /// The token program account is not the SPL Token program.
+    #[error("Invalid token program")]
+    InvalidTokenProgram,
+    /// The associated token program account is not the SPL Associated Token
+    /// Account program.
+    #[error("Invalid associated token program")]
+    InvalidAssociatedTokenProgram,
+    /// The system program account is not the system program.
+    #[error("Invalid system program")]
+    InvalidSystemProgram,
+    /// The rent account is not the rent sysvar.
+    #[error("Invalid rent sysvar")]
+    InvalidRentSysvar,
+    /// The pool stakes and pays rewards in different mints, so rewards cannot
+    /// be compounded nor a legacy stake account moved into it.
+    #[error("Stake and reward mints differ")]
+    CompoundMintMismatch,
+
+    // 40
+    /// The admin was renounced, so privileged instructions are disabled.
+    #[error("Admin renounced")]
+    AdminRenounced,
+    /// The signer is not the admin proposed by `ProposeAdmin`.
+    #[error("Signer is not the pending admin")]
+    NotPendingAdmin,
+    /// The instruction is paused.
+    #[error("Instruction paused")]
+    Paused,
+    /// The pause flags contain unknown bits.
+    #[error("Invalid pause flags")]
+    InvalidPauseFlags,
+    /// The guardian tried to clear a pause flag, which only the admin can do.
+    #[error("Guardian cannot unpause")]
+    GuardianCannotUnpause,
+
+    // 45
+    /// The emission schedule window, rate steps or halving period are out of
+    /// range.
+    #[error("Invalid emission schedule")]
+    InvalidEmissionSchedule,
+    /// The account is not an initialized token mint.
+    #[error("Invalid mint")]
+    InvalidMint,
+    /// The stake account holds no lock to release.
+    #[error("No expired lock")]
+    NoExpiredLock,
+    /// A started rate step of the emission schedule overrides the reward rate.
+    #[error("Rate step in force")]
+    RateStepInForce,
+}
+
+impl From<MarketplaceError> for ProgramError {
+    fn from(e: MarketplaceError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for MarketplaceError {
+    fn type_of() -> &'static str {
+        "MarketplaceError"
+    }
+}
+
+impl PrintProgramError for MarketplaceError {
+    fn print<E>(&self)
+    where
+        E: 'static
+            + std::error::Error
+            + DecodeError<E>
+            + PrintProgramError
+            + num_traits::FromPrimitive,
+    {
+        msg!(&self.to_string());
+    }
+}
@@ -0,0 +1,308 @@
+//! Program events
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{entrypoint::ProgramResult, log::sol_log_data, pubkey::Pubkey};
+
+use crate::{LockTier, RateChange};
+
+/// Prefix of the log lines written by `sol_log_data`
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// Events logged by the Marketplace program, one per successful instruction
+/// that writes state.
+///
+/// Each event is written with `sol_log_data` as a single Borsh-encoded field.
+/// The variant index is the event discriminator, so new events are appended.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum MarketplaceEvent {
+    /// The program config was initialized.
+    Initialized { admin: Pubkey },
+    /// A pool was created.
+    PoolCreated {
+        pool: Pubkey,
+        pool_id: u64,
+        stake_token_mint: Pubkey,
+        reward_token_mint: Pubkey,
+        reward_rate: u64,
+    },
+    /// The vault PDA of a pool was created.
+    VaultGenerated { pool: Pubkey, vault: Pubkey },
+    /// Tokens were added to a stake position.
+    Staked {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+        lock_end: i64,
+    },
+    /// Principal was withdrawn, `penalty` of it burned for leaving a lock early.
+    Withdrawn {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+        penalty: u64,
+    },
+    /// Accrued rewards were paid out.
+    Claimed {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+    },
+    /// Reward tokens were added to the pool budget.
+    RewardsFunded {
+        pool: Pubkey,
+        funder: Pubkey,
+        amount: u64,
+    },
+    /// The lock tiers and early withdraw penalty of a pool were replaced.
+    LockTiersSet {
+        pool: Pubkey,
+        lock_tiers: Vec<LockTier>,
+        early_withdraw_penalty_bps: u64,
+    },
+    /// Principal entered the unstake cooldown.
+    UnstakeRequested {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+    },
+    /// Principal that finished its cooldown was paid out.
+    UnstakeCompleted {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+    },
+    /// Principal in cooldown was returned to the stake.
+    UnstakeCancelled {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+    },
+    /// The unstake cooldown of a pool was changed.
+    UnstakeCooldownSet { pool: Pubkey, unstake_cooldown: i64 },
+    /// An empty stake account was closed.
+    StakeAccountClosed { pool: Pubkey, staker: Pubkey },
+    /// A stake account was upgraded to the current layout.
+    StakeAccountMigrated { pool: Pubkey, staker: Pubkey },
+    /// Accrued rewards were added to the staked principal.
+    Compounded {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+    },
+    /// A new admin was proposed.
+    AdminProposed { admin: Pubkey, new_admin: Pubkey },
+    /// The proposed admin accepted and replaced the previous admin.
+    AdminAccepted {
+        previous_admin: Pubkey,
+        admin: Pubkey,
+    },
+    /// The admin was renounced.
+    AdminRenounced { admin: Pubkey },
+    /// The guardian was set, or removed when default.
+    GuardianSet { guardian: Pubkey },
+    /// The pause flags were replaced by the admin or the guardian.
+    PausedSet { authority: Pubkey, paused: u8 },
+    /// The whole principal was withdrawn, forfeiting `forfeited_reward` back
+    /// to the reward budget.
+    EmergencyWithdrawn {
+        pool: Pubkey,
+        staker: Pubkey,
+        amount: u64,
+        forfeited_reward: u64,
+    },
+    /// The reward rate of a pool changed, effective from `start_time`.
+    RewardRateSet {
+        pool: Pubkey,
+        previous_rate: u64,
+        reward_rate: u64,
+        start_time: i64,
+    },
+    /// The emission schedule of a pool was replaced.
+    EmissionScheduleSet {
+        pool: Pubkey,
+        start_time: i64,
+        end_time: i64,
+        halving_period: i64,
+        rate_steps: Vec<RateChange>,
+    },
+    /// The boost of an expired lock was dropped.
+    LockReleased { pool: Pubkey, staker: Pubkey },
+}
+
+impl MarketplaceEvent {
+    /// Logs the event.
+    pub(crate) fn emit(&self) -> ProgramResult {
+        sol_log_data(&[&self.try_to_vec()?]);
+        Ok(())
+    }
+
+    /// Decodes the event of a single `Program data: ` log line, regardless of
+    /// the program that logged it.
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        let data = line.strip_prefix(PROGRAM_DATA)?;
+        let mut fields = data.split(' ');
+        let event = STANDARD.decode(fields.next()?).ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Self::try_from_slice(&event).ok()
+    }
+}
+
+/// Call frame change announced by a runtime log line
+enum Frame {
+    /// `Program <id> invoke [<depth>]`
+    Invoke(Pubkey),
+    /// `Program <id> success` or `Program <id> failed: <error>`
+    Exit(Pubkey),
+}
+
+impl Frame {
+    // parses only the lines the runtime writes, so `Program log: `, `Program
+    // data: ` and `Program return: ` lines never move the frame
+    fn from_log_line(line: &str) -> Option<Self> {
+        let (id, rest) = line.strip_prefix("Program ")?.split_once(' ')?;
+        let id = id.parse::<Pubkey>().ok()?;
+        if rest == "success" || rest.starts_with("failed: ") {
+            return Some(Frame::Exit(id));
+        }
+        rest.strip_prefix("invoke [")?
+            .strip_suffix(']')?
+            .parse::<usize>()
+            .ok()?;
+        Some(Frame::Invoke(id))
+    }
+}
+
+/// Decodes the events logged by `program_id` in the log messages of a
+/// transaction, skipping data logged by any other program, including the
+/// programs it invokes.
+pub fn decode_events<I, S>(program_id: &Pubkey, logs: I) -> Vec<MarketplaceEvent>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut invoke_stack: Vec<Pubkey> = Vec::new();
+    let mut events = Vec::new();
+    for line in logs {
+        let line = line.as_ref();
+        if line.starts_with(PROGRAM_DATA) {
+            if invoke_stack.last() == Some(program_id) {
+                events.extend(MarketplaceEvent::from_log_line(line));
+            }
+            continue;
+        }
+        match Frame::from_log_line(line) {
+            Some(Frame::Invoke(id)) => invoke_stack.push(id),
+            Some(Frame::Exit(id)) if invoke_stack.last() == Some(&id) => {
+                invoke_stack.pop();
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_line(event: &MarketplaceEvent) -> String {
+        format!(
+            "{}{}",
+            PROGRAM_DATA,
+            STANDARD.encode(event.try_to_vec().unwrap())
+        )
+    }
+
+    #[test]
+    fn test_from_log_line() {
+        let event = MarketplaceEvent::Claimed {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount: 42,
+        };
+        assert_eq!(
+            MarketplaceEvent::from_log_line(&data_line(&event)),
+            Some(event)
+        );
+        assert_eq!(MarketplaceEvent::from_log_line("Program log: hi"), None);
+        assert_eq!(MarketplaceEvent::from_log_line("Program data: !!"), None);
+    }
+
+    #[test]
+    fn test_decode_events_skips_other_programs() {
+        let program_id = Pubkey::new_unique();
+        let other_program = Pubkey::new_unique();
+        let staked = MarketplaceEvent::Staked {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount: 10,
+            lock_end: 0,
+        };
+        let withdrawn = MarketplaceEvent::Withdrawn {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount: 10,
+            penalty: 1,
+        };
+        let logs = vec![
+            format!("Program {} invoke [1]", program_id),
+            format!("Program {} invoke [2]", other_program),
+            data_line(&withdrawn),
+            format!("Program {} success", other_program),
+            data_line(&staked),
+            format!(
+                "Program {} consumed 100 of 200000 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+            format!("Program {} invoke [1]", other_program),
+            data_line(&withdrawn),
+            format!(
+                "Program {} failed: custom program error: 0x0",
+                other_program
+            ),
+        ];
+        assert_eq!(decode_events(&program_id, &logs), vec![staked]);
+    }
+
+    #[test]
+    fn test_decode_events_ignores_spoofed_frames() {
+        let program_id = Pubkey::new_unique();
+        let other_program = Pubkey::new_unique();
+        let staked = MarketplaceEvent::Staked {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount: 10,
+            lock_end: 0,
+        };
+        let withdrawn = MarketplaceEvent::Withdrawn {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount: 10,
+            penalty: 1,
+        };
+        // `Program log: ` lines are written by the programs themselves
+        let logs = vec![
+            format!("Program {} invoke [1]", program_id),
+            "Program log: invoke [2]".to_string(),
+            data_line(&staked),
+            format!("Program {} invoke [2]", other_program),
+            "Program log: success".to_string(),
+            format!("Program log: {} success", other_program),
+            "Program log: failed: spoofed".to_string(),
+            data_line(&withdrawn),
+            format!("Program {} success", other_program),
+            format!("Program {} invoke", other_program),
+            format!("Program return: {} AQ==", program_id),
+            data_line(&staked),
+            format!("Program {} success", program_id),
+        ];
+        assert_eq!(
+            decode_events(&program_id, &logs),
+            vec![staked.clone(), staked]
+        );
+    }
+}
@@ -0,0 +1,897 @@
+//! Instruction types and client-side builders
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+    sysvar::rent,
+};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::{
+    find_config_address, find_legacy_stake_address, find_legacy_vault_address, find_pool_address,
+    find_reward_vault_address, find_stake_address, find_vault_address, LockTier, RateChange,
+};
+
+/// Instructions supported by the Marketplace program.
+///
+/// Variants are Borsh-encoded by index, so new instructions are appended.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum MarketplaceInstruction {
+    /// Creates the vault PDA of a pool.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Admin
+    ///   1. `[]` Config PDA
+    ///   2. `[]` Pool PDA
+    ///   3. `[writable]` Vault PDA
+    ///   4. `[]` System program
+    ///   5. `[]` Rent sysvar
+    GenerateVault,
+    /// Stakes `amount` stake tokens into a pool, optionally locking the whole
+    /// position for the duration of the pool's `lock_tier` for boosted rewards.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Staker
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ///   3. `[]` Stake token mint
+    ///   4. `[]` Vault PDA
+    ///   5. `[writable]` Vault stake token account
+    ///   6. `[writable]` Staker stake token account
+    ///   7. `[]` Token program
+    ///   8. `[]` Associated token account program
+    ///   9. `[]` System program
+    ///   10. `[]` Rent sysvar
+    ///   11. `[]` Config PDA
+    Stake { amount: u64, lock_tier: Option<u8> },
+    /// Withdraws `amount` staked tokens from a pool. Fails while the stake is
+    /// locked.
+    ///
+    /// Accounts expected: same as `Stake`.
+    Withdraw { amount: u64 },
+    /// Claims accrued rewards from the reward vault of a pool.
+    ///
+    /// Accounts expected: same as `Stake`, with the reward token mint, the
+    /// reward vault PDA and reward token accounts in place of the stake ones.
+    Claim,
+    /// Creates the program config and makes the signer its admin.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Admin
+    ///   1. `[writable]` Config PDA
+    ///   2. `[]` System program
+    ///   3. `[]` Rent sysvar
+    Initialize,
+    /// Creates a staking pool emitting `reward_rate` reward token base units
+    /// per second, shared pro-rata among stakers, until the funded rewards
+    /// are exhausted.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Admin
+    ///   1. `[]` Config PDA
+    ///   2. `[writable]` Pool PDA
+    ///   3. `[]` Stake token mint
+    ///   4. `[]` Reward token mint
+    ///   5. `[]` System program
+    ///   6. `[]` Rent sysvar
+    CreatePool { pool_id: u64, reward_rate: u64 },
+    /// Transfers `amount` reward tokens into the reward vault of a pool and
+    /// adds them to the pool's reward budget. Anyone may fund a pool.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Funder
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[]` Reward token mint
+    ///   3. `[]` Reward vault PDA
+    ///   4. `[writable]` Reward vault token account
+    ///   5. `[writable]` Funder reward token account
+    ///   6. `[]` Token program
+    ///   7. `[]` Associated token account program
+    ///   8. `[]` System program
+    ///   9. `[]` Rent sysvar
+    FundRewards { amount: u64 },
+    /// Withdraws `amount` staked tokens from a pool before the lock ends,
+    /// burning the pool's early withdraw penalty out of it.
+    ///
+    /// Accounts expected: same as `Stake`, with the stake token mint writable.
+    EarlyWithdraw { amount: u64 },
+    /// Replaces the lock tiers and early withdraw penalty of a pool.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin
+    ///   1. `[]` Config PDA
+    ///   2. `[writable]` Pool PDA
+    SetLockTiers {
+        lock_tiers: Vec<LockTier>,
+        early_withdraw_penalty_bps: u64,
+    },
+    /// Moves `amount` staked tokens into the cooldown queue of the stake
+    /// account, where they stop earning rewards. Restarts the cooldown.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Staker
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ///   3. `[]` Config PDA
+    RequestUnstake { amount: u64 },
+    /// Transfers the pending unstake out once the pool's cooldown elapsed.
+    ///
+    /// Accounts expected: same as `Stake`.
+    CompleteUnstake,
+    /// Re-stakes the pending unstake.
+    ///
+    /// Accounts expected: same as `RequestUnstake`.
+    CancelUnstake,
+    /// Sets the unstake cooldown of a pool in seconds. Zero allows `Withdraw`.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin
+    ///   1. `[]` Config PDA
+    ///   2. `[writable]` Pool PDA
+    SetUnstakeCooldown { unstake_cooldown: i64 },
+    /// Closes an empty stake account and returns its rent to the staker.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Staker
+    ///   1. `[]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    CloseStakeAccount,
+    /// Upgrades a version 1 stake account to the current layout, topping up
+    /// its rent from the staker. Accounts of the original program are moved
+    /// into a pool with `MigrateLegacyStakeAccount` instead.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Staker
+    ///   1. `[]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ///   3. `[]` System program
+    ///   4. `[]` Rent sysvar
+    MigrateStakeAccount,
+    /// Computes the reward a staker could claim now and returns it as a
+    /// little-endian `u64` through the return data, without writing any account.
+    ///
+    /// Accounts expected:
+    ///   0. `[]` Staker
+    ///   1. `[]` Pool PDA
+    ///   2. `[]` Stake PDA
+    GetPendingReward,
+    /// Adds the accrued reward to the staked principal, moving it from the
+    /// reward vault to the vault. Only for pools whose stake and reward mints
+    /// are the same.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Staker
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ///   3. `[]` Stake and reward token mint
+    ///   4. `[]` Vault PDA
+    ///   5. `[writable]` Vault token account, the vault's ATA
+    ///   6. `[]` Reward vault PDA
+    ///   7. `[writable]` Reward vault token account, the reward vault's ATA
+    ///   8. `[]` Token program
+    ///   9. `[]` Config PDA
+    Compound,
+    /// Proposes a new admin, who takes over once they sign `AcceptAdmin`.
+    /// A new proposal replaces the pending one.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin
+    ///   1. `[writable]` Config PDA
+    ProposeAdmin { new_admin: Pubkey },
+    /// Makes the proposed admin the admin.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Pending admin
+    ///   1. `[writable]` Config PDA
+    AcceptAdmin,
+    /// Removes the admin for good, disabling every admin instruction.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin
+    ///   1. `[writable]` Config PDA
+    RenounceAdmin,
+    /// Sets the guardian, who may pause instructions but not unpause them.
+    /// The default pubkey removes the guardian.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin
+    ///   1. `[writable]` Config PDA
+    SetGuardian { guardian: Pubkey },
+    /// Replaces the `PAUSE_*` flags. The admin may set any flags, the
+    /// guardian may only add flags.
+    ///
+    /// Accounts expected:
+    ///   0. `[signer]` Admin or guardian
+    ///   1. `[writable]` Config PDA
+    SetPaused { paused: u8 },
+    /// Transfers the whole principal of a stake account, pending unstake
+    /// included, without penalty and regardless of locks, cooldowns and pause
+    /// flags. Accrued rewards are forfeited back to the reward budget and the
+    /// pool accumulator is left untouched, so a broken reward vault cannot
+    /// hold the principal back.
+    ///
+    /// Accounts expected: same as `Stake`.
+    EmergencyWithdraw,
+    /// Sets the reward token base units a pool emits per second, the yearly
+    /// emission being `reward_rate * SECONDS_PER_YEAR`. Rewards accrued so far
+    /// are emitted at the previous rate first, and the change is recorded in
+    /// the pool's rate history. Fails once a rate step of the emission
+    /// schedule has started, as the steps set the rate from then on.
+    ///
+    /// Accounts expected: same as `SetLockTiers`.
+    SetRewardRate { reward_rate: u64 },
+    /// Replaces the emission schedule of a pool: rewards are only emitted
+    /// between `start_time` and `end_time`, at the latest of `rate_steps`
+    /// that started or else `reward_rate`, halved every `halving_period`
+    /// seconds after `start_time`. Zero disables each bound and the halving.
+    /// Rewards accrued so far are emitted under the previous schedule first.
+    ///
+    /// Accounts expected: same as `SetLockTiers`.
+    SetEmissionSchedule {
+        start_time: i64,
+        end_time: i64,
+        halving_period: i64,
+        rate_steps: Vec<RateChange>,
+    },
+    /// Drops the boost of a lock that ended, settling the reward earned so
+    /// far at the boosted weight. Anyone may send it, so an expired boost
+    /// stops diluting the pool without waiting for the staker.
+    ///
+    /// Accounts expected:
+    ///   0. `[]` Staker
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ReleaseExpiredLock,
+    /// Moves a stake account of the original program, which kept every stake
+    /// outside any pool, into a pool whose stake and reward mint is the single
+    /// mint of the original program. The principal goes to the pool vault and
+    /// the checkpointed reward, as far as the original vault still holds it, to
+    /// the reward vault. Both are added to the staker's position in the pool,
+    /// created if missing, and the legacy account is closed with its rent
+    /// returned to the staker.
+    ///
+    /// Accounts expected:
+    ///   0. `[writable, signer]` Staker
+    ///   1. `[writable]` Pool PDA
+    ///   2. `[writable]` Stake PDA
+    ///   3. `[writable]` Legacy stake account, `["stake", staker]`
+    ///   4. `[]` Stake and reward token mint
+    ///   5. `[]` Legacy vault PDA, `["vault"]`
+    ///   6. `[writable]` Legacy vault token account, the legacy vault's ATA
+    ///   7. `[]` Vault PDA
+    ///   8. `[writable]` Vault token account, the vault's ATA
+    ///   9. `[]` Reward vault PDA
+    ///   10. `[writable]` Reward vault token account, the reward vault's ATA
+    ///   11. `[]` Token program
+    ///   12. `[]` Associated token program
+    ///   13. `[]` System program
+    ///   14. `[]` Rent sysvar
+    ///   15. `[]` Config PDA
+    MigrateLegacyStakeAccount,
+}
+
+impl MarketplaceInstruction {
+    /// Decodes an instruction, rejecting unknown variants, truncated fields
+    /// and trailing bytes.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        Self::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)
+    }
+}
+
+/// Creates an `Initialize` instruction.
+pub fn initialize(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(config, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+        ],
+        data: MarketplaceInstruction::Initialize.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `ProposeAdmin` instruction.
+pub fn propose_admin(program_id: &Pubkey, admin: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: config_accounts(program_id, admin),
+        data: MarketplaceInstruction::ProposeAdmin {
+            new_admin: *new_admin,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Creates an `AcceptAdmin` instruction.
+pub fn accept_admin(program_id: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: config_accounts(program_id, new_admin),
+        data: MarketplaceInstruction::AcceptAdmin.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `RenounceAdmin` instruction.
+pub fn renounce_admin(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: config_accounts(program_id, admin),
+        data: MarketplaceInstruction::RenounceAdmin.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `SetGuardian` instruction.
+pub fn set_guardian(program_id: &Pubkey, admin: &Pubkey, guardian: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: config_accounts(program_id, admin),
+        data: MarketplaceInstruction::SetGuardian {
+            guardian: *guardian,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Creates a `SetPaused` instruction signed by the admin or the guardian.
+pub fn set_paused(program_id: &Pubkey, authority: &Pubkey, paused: u8) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: config_accounts(program_id, authority),
+        data: MarketplaceInstruction::SetPaused { paused }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `CreatePool` instruction.
+pub fn create_pool(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    reward_mint: &Pubkey,
+    reward_rate: u64,
+) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new(pool, false),
+            AccountMeta::new_readonly(*stake_mint, false),
+            AccountMeta::new_readonly(*reward_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+        ],
+        data: MarketplaceInstruction::CreatePool {
+            pool_id,
+            reward_rate,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Creates a `GenerateVault` instruction.
+pub fn generate_vault(program_id: &Pubkey, admin: &Pubkey, pool_id: u64) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (vault, _) = find_vault_address(program_id, &pool);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(config, false),
+            AccountMeta::new_readonly(pool, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+        ],
+        data: MarketplaceInstruction::GenerateVault.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `Stake` instruction.
+pub fn stake(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+    amount: u64,
+    lock_tier: Option<u8>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: staker_accounts(program_id, pool_id, stake_mint, staker),
+        data: MarketplaceInstruction::Stake { amount, lock_tier }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `Withdraw` instruction.
+pub fn withdraw(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: staker_accounts(program_id, pool_id, stake_mint, staker),
+        data: MarketplaceInstruction::Withdraw { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates an `EarlyWithdraw` instruction.
+pub fn early_withdraw(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = staker_accounts(program_id, pool_id, stake_mint, staker);
+    // the penalty is burned, which writes the mint supply
+    accounts[3].is_writable = true;
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: MarketplaceInstruction::EarlyWithdraw { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `SetLockTiers` instruction.
+pub fn set_lock_tiers(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pool_id: u64,
+    lock_tiers: Vec<LockTier>,
+    early_withdraw_penalty_bps: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: admin_pool_accounts(program_id, admin, pool_id),
+        data: MarketplaceInstruction::SetLockTiers {
+            lock_tiers,
+            early_withdraw_penalty_bps,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Creates a `RequestUnstake` instruction.
+pub fn request_unstake(
+    program_id: &Pubkey,
+    pool_id: u64,
+    staker: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: unstake_accounts(program_id, pool_id, staker),
+        data: MarketplaceInstruction::RequestUnstake { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `CompleteUnstake` instruction.
+pub fn complete_unstake(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: staker_accounts(program_id, pool_id, stake_mint, staker),
+        data: MarketplaceInstruction::CompleteUnstake
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates an `EmergencyWithdraw` instruction.
+pub fn emergency_withdraw(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: staker_accounts(program_id, pool_id, stake_mint, staker),
+        data: MarketplaceInstruction::EmergencyWithdraw
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `CancelUnstake` instruction.
+pub fn cancel_unstake(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: unstake_accounts(program_id, pool_id, staker),
+        data: MarketplaceInstruction::CancelUnstake.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `SetUnstakeCooldown` instruction.
+pub fn set_unstake_cooldown(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pool_id: u64,
+    unstake_cooldown: i64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: admin_pool_accounts(program_id, admin, pool_id),
+        data: MarketplaceInstruction::SetUnstakeCooldown { unstake_cooldown }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `SetRewardRate` instruction.
+pub fn set_reward_rate(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pool_id: u64,
+    reward_rate: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: admin_pool_accounts(program_id, admin, pool_id),
+        data: MarketplaceInstruction::SetRewardRate { reward_rate }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `SetEmissionSchedule` instruction.
+pub fn set_emission_schedule(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    pool_id: u64,
+    start_time: i64,
+    end_time: i64,
+    halving_period: i64,
+    rate_steps: Vec<RateChange>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: admin_pool_accounts(program_id, admin, pool_id),
+        data: MarketplaceInstruction::SetEmissionSchedule {
+            start_time,
+            end_time,
+            halving_period,
+            rate_steps,
+        }
+        .try_to_vec()
+        .unwrap(),
+    }
+}
+
+/// Creates a `CloseStakeAccount` instruction.
+pub fn close_stake_account(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Instruction {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new_readonly(pool, false),
+            AccountMeta::new(stake_data, false),
+        ],
+        data: MarketplaceInstruction::CloseStakeAccount
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `MigrateStakeAccount` instruction.
+pub fn migrate_stake_account(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Instruction {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new_readonly(pool, false),
+            AccountMeta::new(stake_data, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+        ],
+        data: MarketplaceInstruction::MigrateStakeAccount
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `GetPendingReward` instruction.
+pub fn get_pending_reward(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Instruction {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*staker, false),
+            AccountMeta::new_readonly(pool, false),
+            AccountMeta::new_readonly(stake_data, false),
+        ],
+        data: MarketplaceInstruction::GetPendingReward
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `ReleaseExpiredLock` instruction.
+pub fn release_expired_lock(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Instruction {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*staker, false),
+            AccountMeta::new(pool, false),
+            AccountMeta::new(stake_data, false),
+        ],
+        data: MarketplaceInstruction::ReleaseExpiredLock
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `MigrateLegacyStakeAccount` instruction.
+pub fn migrate_legacy_stake_account(
+    program_id: &Pubkey,
+    pool_id: u64,
+    mint: &Pubkey,
+    staker: &Pubkey,
+) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    let (legacy_stake_data, _) = find_legacy_stake_address(program_id, staker);
+    let (legacy_vault, _) = find_legacy_vault_address(program_id);
+    let (vault, _) = find_vault_address(program_id, &pool);
+    let (reward_vault, _) = find_reward_vault_address(program_id, &pool);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*staker, true),
+            AccountMeta::new(pool, false),
+            AccountMeta::new(stake_data, false),
+            AccountMeta::new(legacy_stake_data, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(legacy_vault, false),
+            AccountMeta::new(get_associated_token_address(&legacy_vault, mint), false),
+            AccountMeta::new_readonly(vault, false),
+            AccountMeta::new(get_associated_token_address(&vault, mint), false),
+            AccountMeta::new_readonly(reward_vault, false),
+            AccountMeta::new(get_associated_token_address(&reward_vault, mint), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+        data: MarketplaceInstruction::MigrateLegacyStakeAccount
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+/// Creates a `Compound` instruction.
+pub fn compound(program_id: &Pubkey, pool_id: u64, mint: &Pubkey, staker: &Pubkey) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    let (vault, _) = find_vault_address(program_id, &pool);
+    let (reward_vault, _) = find_reward_vault_address(program_id, &pool);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*staker, true),
+            AccountMeta::new(pool, false),
+            AccountMeta::new(stake_data, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(vault, false),
+            AccountMeta::new(get_associated_token_address(&vault, mint), false),
+            AccountMeta::new_readonly(reward_vault, false),
+            AccountMeta::new(get_associated_token_address(&reward_vault, mint), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+        data: MarketplaceInstruction::Compound.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `Claim` instruction.
+pub fn claim(
+    program_id: &Pubkey,
+    pool_id: u64,
+    reward_mint: &Pubkey,
+    staker: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: reward_accounts(program_id, pool_id, reward_mint, staker),
+        data: MarketplaceInstruction::Claim.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `FundRewards` instruction.
+pub fn fund_rewards(
+    program_id: &Pubkey,
+    pool_id: u64,
+    reward_mint: &Pubkey,
+    funder: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (reward_vault, _) = find_reward_vault_address(program_id, &pool);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(pool, false),
+            AccountMeta::new_readonly(*reward_mint, false),
+            AccountMeta::new_readonly(reward_vault, false),
+            AccountMeta::new(
+                get_associated_token_address(&reward_vault, reward_mint),
+                false,
+            ),
+            AccountMeta::new(get_associated_token_address(funder, reward_mint), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(rent::id(), false),
+        ],
+        data: MarketplaceInstruction::FundRewards { amount }
+            .try_to_vec()
+            .unwrap(),
+    }
+}
+
+// account list shared by the instructions updating the admin
+fn config_accounts(program_id: &Pubkey, signer: &Pubkey) -> Vec<AccountMeta> {
+    let (config, _) = find_config_address(program_id);
+    vec![
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(config, false),
+    ]
+}
+
+// account list shared by admin instructions updating a pool
+fn admin_pool_accounts(program_id: &Pubkey, admin: &Pubkey, pool_id: u64) -> Vec<AccountMeta> {
+    let (config, _) = find_config_address(program_id);
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(config, false),
+        AccountMeta::new(pool, false),
+    ]
+}
+
+// account list shared by request_unstake and cancel_unstake
+fn unstake_accounts(program_id: &Pubkey, pool_id: u64, staker: &Pubkey) -> Vec<AccountMeta> {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (stake_data, _) = find_stake_address(program_id, &pool, staker);
+    let (config, _) = find_config_address(program_id);
+    vec![
+        AccountMeta::new_readonly(*staker, true),
+        AccountMeta::new(pool, false),
+        AccountMeta::new(stake_data, false),
+        AccountMeta::new_readonly(config, false),
+    ]
+}
+
+// account list shared by stake, withdraw and complete_unstake
+fn staker_accounts(
+    program_id: &Pubkey,
+    pool_id: u64,
+    stake_mint: &Pubkey,
+    staker: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (vault, _) = find_vault_address(program_id, &pool);
+    vault_accounts(program_id, &pool, &vault, stake_mint, staker)
+}
+
+// account list of claim
+fn reward_accounts(
+    program_id: &Pubkey,
+    pool_id: u64,
+    reward_mint: &Pubkey,
+    staker: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (pool, _) = find_pool_address(program_id, pool_id);
+    let (reward_vault, _) = find_reward_vault_address(program_id, &pool);
+    vault_accounts(program_id, &pool, &reward_vault, reward_mint, staker)
+}
+
+fn vault_accounts(
+    program_id: &Pubkey,
+    pool: &Pubkey,
+    vault: &Pubkey,
+    mint: &Pubkey,
+    staker: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (config, _) = find_config_address(program_id);
+    let (stake_data, _) = find_stake_address(program_id, pool, staker);
+    vec![
+        AccountMeta::new(*staker, true),
+        AccountMeta::new(*pool, false),
+        AccountMeta::new(stake_data, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*vault, false),
+        AccountMeta::new(get_associated_token_address(vault, mint), false),
+        AccountMeta::new(get_associated_token_address(staker, mint), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(rent::id(), false),
+        AccountMeta::new_readonly(config, false),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpack_round_trip() {
+        let instruction = MarketplaceInstruction::Stake {
+            amount: 10,
+            lock_tier: Some(1),
+        };
+        let data = instruction.try_to_vec().unwrap();
+        assert_eq!(MarketplaceInstruction::unpack(&data), Ok(instruction));
+    }
+
+    #[test]
+    fn test_unpack_rejects_malformed_data() {
+        let mut data = MarketplaceInstruction::Withdraw { amount: 10 }
+            .try_to_vec()
+            .unwrap();
+
+        // truncated field
+        assert_eq!(
+            MarketplaceInstruction::unpack(&data[..data.len() - 1]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        // empty and unknown variant
+        assert_eq!(
+            MarketplaceInstruction::unpack(&[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            MarketplaceInstruction::unpack(&[u8::MAX]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        // trailing bytes
+        data.push(0);
+        assert_eq!(
+            MarketplaceInstruction::unpack(&data),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+}
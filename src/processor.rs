@@ -0,0 +1,2474 @@
+//! Program state processor
+
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    program::{invoke, invoke_signed, set_return_data},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::{Pubkey, PubkeyError},
+    system_instruction, system_program,
+    sysvar::{self, rent::Rent, Sysvar},
+};
+
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::error::MarketplaceError;
+use crate::event::MarketplaceEvent;
+use crate::instruction::MarketplaceInstruction;
+use crate::state::{
+    load_account, AccountData, ConfigData, EmissionSchedule, LockTier, PoolData, RateChange,
+    StakeData, VaultData, BASIS_POINTS, LEGACY_STAKE_DATA_SIZE, MAX_LOCK_TIERS, MAX_RATE_STEPS,
+    PAUSE_ALL, PAUSE_CLAIM, PAUSE_STAKE, PAUSE_WITHDRAW, RATE_HISTORY_LEN,
+};
+use crate::{
+    create_config_address, create_pool_address, create_reward_vault_address, create_stake_address,
+    create_vault_address, find_config_address, find_legacy_stake_address,
+    find_legacy_vault_address, find_pool_address, find_reward_vault_address, find_stake_address,
+    find_vault_address, CONFIG_PREFIX, POOL_PREFIX, REWARD_VAULT_PREFIX, STAKE_PREFIX,
+    VAULT_PREFIX,
+};
+
+// Program entrypoint's implementation
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let instruction = MarketplaceInstruction::unpack(instruction_data)?;
+
+    match instruction {
+        MarketplaceInstruction::GenerateVault => process_generate_vault(program_id, accounts),
+        MarketplaceInstruction::Stake { amount, lock_tier } => {
+            process_stake(program_id, accounts, amount, lock_tier)
+        }
+        MarketplaceInstruction::Withdraw { amount } => {
+            process_withdraw(program_id, accounts, amount, false)
+        }
+        MarketplaceInstruction::Claim => process_claim(program_id, accounts),
+        MarketplaceInstruction::Initialize => process_initialize(program_id, accounts),
+        MarketplaceInstruction::CreatePool {
+            pool_id,
+            reward_rate,
+        } => process_create_pool(program_id, accounts, pool_id, reward_rate),
+        MarketplaceInstruction::FundRewards { amount } => {
+            process_fund_rewards(program_id, accounts, amount)
+        }
+        MarketplaceInstruction::EarlyWithdraw { amount } => {
+            process_withdraw(program_id, accounts, amount, true)
+        }
+        MarketplaceInstruction::SetLockTiers {
+            lock_tiers,
+            early_withdraw_penalty_bps,
+        } => process_set_lock_tiers(program_id, accounts, lock_tiers, early_withdraw_penalty_bps),
+        MarketplaceInstruction::RequestUnstake { amount } => {
+            process_request_unstake(program_id, accounts, amount)
+        }
+        MarketplaceInstruction::CompleteUnstake => process_complete_unstake(program_id, accounts),
+        MarketplaceInstruction::CancelUnstake => process_cancel_unstake(program_id, accounts),
+        MarketplaceInstruction::SetUnstakeCooldown { unstake_cooldown } => {
+            process_set_unstake_cooldown(program_id, accounts, unstake_cooldown)
+        }
+        MarketplaceInstruction::CloseStakeAccount => {
+            process_close_stake_account(program_id, accounts)
+        }
+        MarketplaceInstruction::MigrateStakeAccount => {
+            process_migrate_stake_account(program_id, accounts)
+        }
+        MarketplaceInstruction::GetPendingReward => {
+            process_get_pending_reward(program_id, accounts)
+        }
+        MarketplaceInstruction::Compound => process_compound(program_id, accounts),
+        MarketplaceInstruction::ProposeAdmin { new_admin } => {
+            process_propose_admin(program_id, accounts, new_admin)
+        }
+        MarketplaceInstruction::AcceptAdmin => process_accept_admin(program_id, accounts),
+        MarketplaceInstruction::RenounceAdmin => process_renounce_admin(program_id, accounts),
+        MarketplaceInstruction::SetGuardian { guardian } => {
+            process_set_guardian(program_id, accounts, guardian)
+        }
+        MarketplaceInstruction::SetPaused { paused } => {
+            process_set_paused(program_id, accounts, paused)
+        }
+        MarketplaceInstruction::EmergencyWithdraw => {
+            process_emergency_withdraw(program_id, accounts)
+        }
+        MarketplaceInstruction::SetRewardRate { reward_rate } => {
+            process_set_reward_rate(program_id, accounts, reward_rate)
+        }
+        MarketplaceInstruction::SetEmissionSchedule {
+            start_time,
+            end_time,
+            halving_period,
+            rate_steps,
+        } => process_set_emission_schedule(
+            program_id,
+            accounts,
+            start_time,
+            end_time,
+            halving_period,
+            rate_steps,
+        ),
+        MarketplaceInstruction::ReleaseExpiredLock => {
+            process_release_expired_lock(program_id, accounts)
+        }
+        MarketplaceInstruction::MigrateLegacyStakeAccount => {
+            process_migrate_legacy_stake_account(program_id, accounts)
+        }
+    }
+}
+
+// rejects impostor program and sysvar accounts before they are handed to a CPI
+fn check_program_account(
+    info: &AccountInfo,
+    expected: &Pubkey,
+    error: MarketplaceError,
+) -> ProgramResult {
+    if info.key != expected {
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+// compares an account with a PDA recreated from its stored bump
+fn check_address(
+    info: &AccountInfo,
+    address: Result<Pubkey, PubkeyError>,
+    error: MarketplaceError,
+) -> ProgramResult {
+    match address {
+        Ok(address) if address == *info.key => Ok(()),
+        _ => Err(error.into()),
+    }
+}
+
+// checks a token account by its unpacked owner and mint instead of deriving
+// its associated address. A missing account is still bound to the associated
+// address of `owner`, where it gets created; the derivation is then only paid
+// next to the far costlier account creation.
+fn check_token_account(
+    info: &AccountInfo,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    error: MarketplaceError,
+) -> ProgramResult {
+    if *info.owner == system_program::id() && info.data_is_empty() {
+        if *info.key != get_associated_token_address(owner, mint) {
+            return Err(error.into());
+        }
+        return Ok(());
+    }
+    if *info.owner != spl_token::id() {
+        return Err(error.into());
+    }
+    match spl_token::state::Account::unpack(&info.data.borrow()) {
+        Ok(token) if token.owner == *owner && token.mint == *mint => Ok(()),
+        _ => Err(error.into()),
+    }
+}
+
+// checks that `info` is an initialized mint of the token program, which a
+// token account of the same owner is not
+fn check_mint_account(info: &AccountInfo) -> ProgramResult {
+    if *info.owner != spl_token::id() {
+        return Err(MarketplaceError::InvalidMint.into());
+    }
+    match spl_token::state::Mint::unpack(&info.data.borrow()) {
+        Ok(mint) if mint.is_initialized => Ok(()),
+        _ => Err(MarketplaceError::InvalidMint.into()),
+    }
+}
+
+fn check_signer(info: &AccountInfo) -> ProgramResult {
+    if !info.is_signer {
+        return Err(MarketplaceError::MissingSignature.into());
+    }
+    Ok(())
+}
+
+// loads the program config and checks it sits at the config PDA
+fn load_config(program_id: &Pubkey, config_info: &AccountInfo) -> Result<ConfigData, ProgramError> {
+    let config = load_account::<ConfigData>(program_id, config_info)?;
+    check_address(
+        config_info,
+        create_config_address(program_id, config.bump),
+        MarketplaceError::InvalidConfigAccount,
+    )?;
+    Ok(config)
+}
+
+// loads a pool and checks it sits at the address derived from its id
+fn load_pool(program_id: &Pubkey, pool_info: &AccountInfo) -> Result<PoolData, ProgramError> {
+    let pool = load_account::<PoolData>(program_id, pool_info)?;
+    check_address(
+        pool_info,
+        create_pool_address(program_id, pool.pool_id, pool.bump),
+        MarketplaceError::InvalidPoolAccount,
+    )?;
+    Ok(pool)
+}
+
+fn check_admin(admin: &AccountInfo, config: &ConfigData) -> ProgramResult {
+    check_signer(admin)?;
+    if config.admin == Pubkey::default() {
+        return Err(MarketplaceError::AdminRenounced.into());
+    }
+    if *admin.key != config.admin {
+        return Err(MarketplaceError::Unauthorized.into());
+    }
+    Ok(())
+}
+
+// loads the stake account of `staker` in the pool, checking its address with the stored bump
+fn load_stake(
+    program_id: &Pubkey,
+    pool_info: &AccountInfo,
+    staker: &AccountInfo,
+    stake_info: &AccountInfo,
+) -> Result<StakeData, ProgramError> {
+    let mut stake_data = load_account::<StakeData>(program_id, stake_info)?;
+    // accounts not migrated to version 2 carry no bump yet
+    if stake_data.bump == 0 {
+        stake_data.bump = find_stake_address(program_id, pool_info.key, staker.key).1;
+    }
+    check_address(
+        stake_info,
+        create_stake_address(program_id, pool_info.key, staker.key, stake_data.bump),
+        MarketplaceError::InvalidStakeAccount,
+    )?;
+    if *staker.key != stake_data.staker {
+        return Err(MarketplaceError::StakerMismatch.into());
+    }
+    Ok(stake_data)
+}
+
+// creates a program-owned PDA of `size` bytes, paying its rent from `payer`
+fn create_pda_account<'info>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    rent_info: &AccountInfo<'info>,
+    size: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = &Rent::from_account_info(rent_info)?;
+    let required_lamports = rent
+        .minimum_balance(size)
+        .max(1)
+        .saturating_sub(account.lamports());
+    invoke(
+        &system_instruction::transfer(payer.key, account.key, required_lamports),
+        &[payer.clone(), account.clone(), system_program.clone()],
+    )?;
+    invoke_signed(
+        &system_instruction::allocate(account.key, size as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    Ok(())
+}
+
+// creates the stake PDA of `staker` in the pool and returns its empty position
+fn create_stake_account<'info>(
+    program_id: &Pubkey,
+    staker: &AccountInfo<'info>,
+    pool_info: &AccountInfo<'info>,
+    stake_info: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    rent_info: &AccountInfo<'info>,
+    timestamp: i64,
+) -> Result<StakeData, ProgramError> {
+    let (stake_address, stake_bump) = find_stake_address(program_id, pool_info.key, staker.key);
+    if *stake_info.key != stake_address {
+        return Err(MarketplaceError::InvalidStakeAccount.into());
+    }
+    create_pda_account(
+        program_id,
+        staker,
+        stake_info,
+        system_program,
+        rent_info,
+        StakeData::LEN,
+        &[
+            STAKE_PREFIX.as_bytes(),
+            &pool_info.key.to_bytes(),
+            &staker.key.to_bytes(),
+            &[stake_bump],
+        ],
+    )?;
+
+    Ok(StakeData {
+        pool: *pool_info.key,
+        staker: *staker.key,
+        amount: 0,
+        reward_debt: 0,
+        remained_reward: 0,
+        last_claim_time: timestamp,
+        lock_end: 0,
+        reward_multiplier_bps: BASIS_POINTS,
+        pending_unstake: 0,
+        unstake_requested_at: 0,
+        bump: stake_bump,
+    })
+}
+
+// hands a program-owned account back to the system program, its rent going to `recipient`
+fn close_account(account: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+    account.data.borrow_mut().fill(0);
+    account.realloc(0, false)?;
+    account.assign(&system_program::id());
+
+    let lamports = account.lamports();
+    **account.lamports.borrow_mut() = 0;
+    **recipient.lamports.borrow_mut() = recipient
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    Ok(())
+}
+
+// moves `amount` out of the vault of the original program, signed by its PDA
+fn transfer_from_legacy_vault<'info>(
+    token_program: &AccountInfo<'info>,
+    legacy_vault: &AccountInfo<'info>,
+    legacy_vault_bump: u8,
+    legacy_vault_token: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    amount: u64,
+) -> ProgramResult {
+    if amount == 0 {
+        return Ok(());
+    }
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            legacy_vault_token.key,
+            destination.key,
+            legacy_vault.key,
+            &[],
+            amount,
+        )?,
+        &[
+            legacy_vault_token.clone(),
+            destination.clone(),
+            legacy_vault.clone(),
+            token_program.clone(),
+        ],
+        &[&[VAULT_PREFIX.as_bytes(), &[legacy_vault_bump]]],
+    )
+}
+
+// creates the associated token account of `wallet` unless it already exists
+#[allow(clippy::too_many_arguments)]
+fn create_token_account_if_missing<'info>(
+    payer: &AccountInfo<'info>,
+    token_account: &AccountInfo<'info>,
+    wallet: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    associated_token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    rent_info: &AccountInfo<'info>,
+) -> ProgramResult {
+    if token_account.owner == token_program.key {
+        return Ok(());
+    }
+    invoke(
+        &spl_associated_token_account::create_associated_token_account(
+            payer.key, wallet.key, mint.key,
+        ),
+        &[
+            payer.clone(),
+            token_account.clone(),
+            wallet.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            rent_info.clone(),
+            associated_token_program.clone(),
+        ],
+    )
+}
+
+/// The pool vault an instruction moves tokens through
+#[derive(Clone, Copy)]
+enum Vault {
+    /// Holds the staked principal
+    Stake,
+    /// Holds the funded rewards
+    Reward,
+}
+
+impl Vault {
+    fn prefix(self) -> &'static str {
+        match self {
+            Vault::Stake => VAULT_PREFIX,
+            Vault::Reward => REWARD_VAULT_PREFIX,
+        }
+    }
+
+    // the vault bump stored on the pool at creation
+    fn bump(self, pool: &PoolData) -> u8 {
+        match self {
+            Vault::Stake => pool.vault_bump,
+            Vault::Reward => pool.reward_vault_bump,
+        }
+    }
+
+    fn create_address(
+        self,
+        program_id: &Pubkey,
+        pool: &Pubkey,
+        bump: u8,
+    ) -> Result<Pubkey, PubkeyError> {
+        match self {
+            Vault::Stake => create_vault_address(program_id, pool, bump),
+            Vault::Reward => create_reward_vault_address(program_id, pool, bump),
+        }
+    }
+
+    // checks the vault PDA of the pool. The stake vault must also carry the
+    // `VaultData` written by `GenerateVault`; the reward vault is a bare
+    // signing PDA that is never allocated, so its address is all there is.
+    fn check_vault(
+        self,
+        program_id: &Pubkey,
+        pool_info: &AccountInfo,
+        pool: &PoolData,
+        vault: &AccountInfo,
+    ) -> ProgramResult {
+        check_address(
+            vault,
+            self.create_address(program_id, pool_info.key, self.bump(pool)),
+            MarketplaceError::InvalidVaultAccount,
+        )?;
+        if let Vault::Stake = self {
+            let vault_data = load_account::<VaultData>(program_id, vault)?;
+            if vault_data.pool != *pool_info.key {
+                return Err(MarketplaceError::InvalidVaultAccount.into());
+            }
+        }
+        Ok(())
+    }
+
+    // checks `mint` is the pool mint held by the vault
+    fn check_mint(self, pool: &PoolData, mint: &AccountInfo) -> ProgramResult {
+        let (expected, error) = match self {
+            Vault::Stake => (&pool.stake_token_mint, MarketplaceError::InvalidStakeMint),
+            Vault::Reward => (&pool.reward_token_mint, MarketplaceError::InvalidRewardMint),
+        };
+        if mint.key != expected {
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+/// System program and rent sysvar, passed by the instructions creating accounts
+struct SystemAccounts<'a, 'info> {
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> SystemAccounts<'a, 'info> {
+    fn parse(
+        accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    ) -> Result<Self, ProgramError> {
+        let system_program = next_account_info(accounts_iter)?;
+        let rent_info = next_account_info(accounts_iter)?;
+
+        check_program_account(
+            system_program,
+            &system_program::id(),
+            MarketplaceError::InvalidSystemProgram,
+        )?;
+        check_program_account(
+            rent_info,
+            &sysvar::rent::id(),
+            MarketplaceError::InvalidRentSysvar,
+        )?;
+
+        Ok(Self {
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Token and associated token programs followed by the `SystemAccounts`,
+/// passed by the instructions moving tokens into possibly missing accounts
+struct TokenPrograms<'a, 'info> {
+    token_program: &'a AccountInfo<'info>,
+    associated_token_program: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> TokenPrograms<'a, 'info> {
+    fn parse(
+        accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    ) -> Result<Self, ProgramError> {
+        let token_program = next_account_info(accounts_iter)?;
+        let associated_token_program = next_account_info(accounts_iter)?;
+
+        check_program_account(
+            token_program,
+            &spl_token::id(),
+            MarketplaceError::InvalidTokenProgram,
+        )?;
+        check_program_account(
+            associated_token_program,
+            &spl_associated_token_account::id(),
+            MarketplaceError::InvalidAssociatedTokenProgram,
+        )?;
+        let SystemAccounts {
+            system_program,
+            rent_info,
+        } = SystemAccounts::parse(accounts_iter)?;
+
+        Ok(Self {
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of `Stake`, `Withdraw`, `EarlyWithdraw`, `Claim`, `CompleteUnstake`
+/// and `EmergencyWithdraw`
+struct VaultAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+    mint: &'a AccountInfo<'info>,
+    vault: &'a AccountInfo<'info>,
+    vault_bump: u8,
+    vault_token: &'a AccountInfo<'info>,
+    staker_token: &'a AccountInfo<'info>,
+    token_program: &'a AccountInfo<'info>,
+    associated_token_program: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+    config: ConfigData,
+}
+
+impl<'a, 'info> VaultAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+        vault_kind: Vault,
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vault_token = next_account_info(accounts_iter)?;
+        let staker_token = next_account_info(accounts_iter)?;
+
+        let TokenPrograms {
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        } = TokenPrograms::parse(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+        let pool = load_pool(program_id, pool_info)?;
+        let vault_bump = vault_kind.bump(&pool);
+
+        check_signer(staker)?;
+        vault_kind.check_mint(&pool, mint)?;
+        vault_kind.check_vault(program_id, pool_info, &pool, vault)?;
+        check_token_account(
+            vault_token,
+            vault.key,
+            mint.key,
+            MarketplaceError::InvalidVaultTokenAccount,
+        )?;
+        check_token_account(
+            staker_token,
+            staker.key,
+            mint.key,
+            MarketplaceError::InvalidUserTokenAccount,
+        )?;
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+            mint,
+            vault,
+            vault_bump,
+            vault_token,
+            staker_token,
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+            config,
+        })
+    }
+
+    fn create_staker_token_account(&self) -> ProgramResult {
+        create_token_account_if_missing(
+            self.staker,
+            self.staker_token,
+            self.staker,
+            self.mint,
+            self.token_program,
+            self.associated_token_program,
+            self.system_program,
+            self.rent_info,
+        )
+    }
+
+    // burns the early exit penalty out of the stake vault
+    fn burn_penalty(&self, penalty: u64) -> ProgramResult {
+        if penalty == 0 {
+            return Ok(());
+        }
+        invoke_signed(
+            &spl_token::instruction::burn(
+                self.token_program.key,
+                self.vault_token.key,
+                self.mint.key,
+                self.vault.key,
+                &[],
+                penalty,
+            )?,
+            &[
+                self.vault_token.clone(),
+                self.mint.clone(),
+                self.vault.clone(),
+                self.token_program.clone(),
+            ],
+            &[&[
+                VAULT_PREFIX.as_bytes(),
+                &self.pool_info.key.to_bytes(),
+                &[self.vault_bump],
+            ]],
+        )
+    }
+
+    // moves `amount` out of the vault to the staker, signed by the vault PDA
+    fn transfer_to_staker(&self, vault_kind: Vault, amount: u64) -> ProgramResult {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                self.token_program.key,
+                self.vault_token.key,
+                self.staker_token.key,
+                self.vault.key,
+                &[],
+                amount,
+            )?,
+            &[
+                self.vault_token.clone(),
+                self.staker_token.clone(),
+                self.vault.clone(),
+                self.token_program.clone(),
+            ],
+            &[&[
+                vault_kind.prefix().as_bytes(),
+                &self.pool_info.key.to_bytes(),
+                &[self.vault_bump],
+            ]],
+        )
+    }
+}
+
+/// Accounts of `FundRewards`
+struct FundRewardsAccounts<'a, 'info> {
+    funder: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    mint: &'a AccountInfo<'info>,
+    vault: &'a AccountInfo<'info>,
+    vault_token: &'a AccountInfo<'info>,
+    funder_token: &'a AccountInfo<'info>,
+    token_program: &'a AccountInfo<'info>,
+    associated_token_program: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> FundRewardsAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let funder = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vault_token = next_account_info(accounts_iter)?;
+        let funder_token = next_account_info(accounts_iter)?;
+
+        let TokenPrograms {
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        } = TokenPrograms::parse(accounts_iter)?;
+
+        let pool = load_pool(program_id, pool_info)?;
+
+        check_signer(funder)?;
+        Vault::Reward.check_mint(&pool, mint)?;
+        Vault::Reward.check_vault(program_id, pool_info, &pool, vault)?;
+        check_token_account(
+            vault_token,
+            vault.key,
+            mint.key,
+            MarketplaceError::InvalidVaultTokenAccount,
+        )?;
+        check_token_account(
+            funder_token,
+            funder.key,
+            mint.key,
+            MarketplaceError::InvalidUserTokenAccount,
+        )?;
+
+        Ok(Self {
+            funder,
+            pool_info,
+            pool,
+            mint,
+            vault,
+            vault_token,
+            funder_token,
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of `Compound`
+struct CompoundAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+    vault_token: &'a AccountInfo<'info>,
+    reward_vault: &'a AccountInfo<'info>,
+    reward_vault_token: &'a AccountInfo<'info>,
+    token_program: &'a AccountInfo<'info>,
+    config: ConfigData,
+}
+
+impl<'a, 'info> CompoundAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vault_token = next_account_info(accounts_iter)?;
+        let reward_vault = next_account_info(accounts_iter)?;
+        let reward_vault_token = next_account_info(accounts_iter)?;
+
+        let token_program = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+
+        check_program_account(
+            token_program,
+            &spl_token::id(),
+            MarketplaceError::InvalidTokenProgram,
+        )?;
+
+        let config = load_config(program_id, config_info)?;
+        let pool = load_pool(program_id, pool_info)?;
+
+        check_signer(staker)?;
+        if pool.stake_token_mint != pool.reward_token_mint {
+            return Err(MarketplaceError::CompoundMintMismatch.into());
+        }
+        Vault::Stake.check_mint(&pool, mint)?;
+        for (vault_kind, vault, vault_token) in [
+            (Vault::Stake, vault, vault_token),
+            (Vault::Reward, reward_vault, reward_vault_token),
+        ] {
+            vault_kind.check_vault(program_id, pool_info, &pool, vault)?;
+            check_token_account(
+                vault_token,
+                vault.key,
+                mint.key,
+                MarketplaceError::InvalidVaultTokenAccount,
+            )?;
+            if vault_token.owner != token_program.key {
+                return Err(MarketplaceError::VaultTokenAccountNotInitialized.into());
+            }
+        }
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+            vault_token,
+            reward_vault,
+            reward_vault_token,
+            token_program,
+            config,
+        })
+    }
+}
+
+/// Accounts of the admin instructions configuring a pool
+struct AdminPoolAccounts<'a, 'info> {
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+}
+
+impl<'a, 'info> AdminPoolAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+        let pool = load_pool(program_id, pool_info)?;
+        check_admin(admin, &config)?;
+
+        Ok(Self { pool_info, pool })
+    }
+}
+
+/// Accounts of the instructions touching only a staker's position:
+/// `CloseStakeAccount` and `MigrateStakeAccount`, and the head of
+/// `UnstakeAccounts`
+struct PositionAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> PositionAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    ) -> Result<Self, ProgramError> {
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+
+        let pool = load_pool(program_id, pool_info)?;
+        check_signer(staker)?;
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+        })
+    }
+}
+
+/// Accounts of `RequestUnstake` and `CancelUnstake`, which move principal
+/// in or out of the cooldown queue and so honor the pause flags
+struct UnstakeAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+    config: ConfigData,
+}
+
+impl<'a, 'info> UnstakeAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let PositionAccounts {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+        } = PositionAccounts::parse(program_id, accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+            config,
+        })
+    }
+}
+
+/// Accounts of `Initialize`
+struct InitializeAccounts<'a, 'info> {
+    admin: &'a AccountInfo<'info>,
+    config_info: &'a AccountInfo<'info>,
+    config_bump: u8,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> InitializeAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+        let SystemAccounts {
+            system_program,
+            rent_info,
+        } = SystemAccounts::parse(accounts_iter)?;
+
+        let (config_address, config_bump) = find_config_address(program_id);
+
+        check_signer(admin)?;
+        if *config_info.key != config_address {
+            return Err(MarketplaceError::InvalidConfigAccount.into());
+        }
+        if config_info.owner == program_id {
+            return Err(MarketplaceError::ConfigAlreadyInitialized.into());
+        }
+
+        Ok(Self {
+            admin,
+            config_info,
+            config_bump,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of the instructions changing only the config: `ProposeAdmin`,
+/// `AcceptAdmin`, `RenounceAdmin`, `SetGuardian` and `SetPaused`. Each
+/// handler checks the authority its own way.
+struct ConfigAccounts<'a, 'info> {
+    authority: &'a AccountInfo<'info>,
+    config_info: &'a AccountInfo<'info>,
+    config: ConfigData,
+}
+
+impl<'a, 'info> ConfigAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let authority = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+
+        Ok(Self {
+            authority,
+            config_info,
+            config,
+        })
+    }
+}
+
+/// Accounts of `CreatePool`
+struct CreatePoolAccounts<'a, 'info> {
+    admin: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool_bump: u8,
+    stake_mint: &'a AccountInfo<'info>,
+    reward_mint: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> CreatePoolAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+        pool_id: u64,
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_mint = next_account_info(accounts_iter)?;
+        let reward_mint = next_account_info(accounts_iter)?;
+        let SystemAccounts {
+            system_program,
+            rent_info,
+        } = SystemAccounts::parse(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+        let (pool_address, pool_bump) = find_pool_address(program_id, pool_id);
+
+        check_admin(admin, &config)?;
+        if *pool_info.key != pool_address {
+            return Err(MarketplaceError::InvalidPoolAccount.into());
+        }
+        if pool_info.owner == program_id {
+            return Err(MarketplaceError::PoolAlreadyCreated.into());
+        }
+        check_mint_account(stake_mint)?;
+        check_mint_account(reward_mint)?;
+
+        Ok(Self {
+            admin,
+            pool_info,
+            pool_bump,
+            stake_mint,
+            reward_mint,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of `GenerateVault`
+struct GenerateVaultAccounts<'a, 'info> {
+    admin: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    vault: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> GenerateVaultAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let SystemAccounts {
+            system_program,
+            rent_info,
+        } = SystemAccounts::parse(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+        let pool = load_pool(program_id, pool_info)?;
+
+        check_address(
+            vault,
+            create_vault_address(program_id, pool_info.key, pool.vault_bump),
+            MarketplaceError::InvalidVaultAccount,
+        )?;
+        if vault.owner == program_id {
+            return Err(MarketplaceError::VaultAlreadyGenerated.into());
+        }
+        check_admin(admin, &config)?;
+
+        Ok(Self {
+            admin,
+            pool_info,
+            pool,
+            vault,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of `MigrateStakeAccount`
+struct MigrateStakeAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    stake_info: &'a AccountInfo<'info>,
+    stake_data: StakeData,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> MigrateStakeAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let PositionAccounts {
+            staker,
+            pool_info,
+            stake_info,
+            ..
+        } = PositionAccounts::parse(program_id, accounts_iter)?;
+        let SystemAccounts {
+            system_program,
+            rent_info,
+        } = SystemAccounts::parse(accounts_iter)?;
+
+        let stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+        if stake_info.data_len() == StakeData::LEN {
+            return Err(MarketplaceError::StakeAccountAlreadyMigrated.into());
+        }
+
+        Ok(Self {
+            staker,
+            pool_info,
+            stake_info,
+            stake_data,
+            system_program,
+            rent_info,
+        })
+    }
+}
+
+/// Accounts of `MigrateLegacyStakeAccount`
+struct MigrateLegacyStakeAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+    legacy_stake_info: &'a AccountInfo<'info>,
+    legacy_stake_data: StakeData,
+    legacy_vault: &'a AccountInfo<'info>,
+    legacy_vault_bump: u8,
+    legacy_vault_token: &'a AccountInfo<'info>,
+    mint: &'a AccountInfo<'info>,
+    vault: &'a AccountInfo<'info>,
+    vault_token: &'a AccountInfo<'info>,
+    reward_vault: &'a AccountInfo<'info>,
+    reward_vault_token: &'a AccountInfo<'info>,
+    token_program: &'a AccountInfo<'info>,
+    associated_token_program: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    rent_info: &'a AccountInfo<'info>,
+    config: ConfigData,
+}
+
+impl<'a, 'info> MigrateLegacyStakeAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+        let legacy_stake_info = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let legacy_vault = next_account_info(accounts_iter)?;
+        let legacy_vault_token = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vault_token = next_account_info(accounts_iter)?;
+        let reward_vault = next_account_info(accounts_iter)?;
+        let reward_vault_token = next_account_info(accounts_iter)?;
+
+        let TokenPrograms {
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        } = TokenPrograms::parse(accounts_iter)?;
+        let config_info = next_account_info(accounts_iter)?;
+
+        let config = load_config(program_id, config_info)?;
+        let pool = load_pool(program_id, pool_info)?;
+
+        check_signer(staker)?;
+        // the original program staked and paid rewards in a single mint
+        if pool.stake_token_mint != pool.reward_token_mint {
+            return Err(MarketplaceError::CompoundMintMismatch.into());
+        }
+        Vault::Stake.check_mint(&pool, mint)?;
+
+        // legacy accounts have no stored bump, and there is no pool to store it on
+        if *legacy_stake_info.key != find_legacy_stake_address(program_id, staker.key).0 {
+            return Err(MarketplaceError::InvalidStakeAccount.into());
+        }
+        let legacy_stake_data = load_account::<StakeData>(program_id, legacy_stake_info)?;
+        if legacy_stake_info.data_len() != LEGACY_STAKE_DATA_SIZE {
+            return Err(MarketplaceError::InvalidStakeData.into());
+        }
+        if *staker.key != legacy_stake_data.staker {
+            return Err(MarketplaceError::StakerMismatch.into());
+        }
+        let (legacy_vault_address, legacy_vault_bump) = find_legacy_vault_address(program_id);
+        if *legacy_vault.key != legacy_vault_address {
+            return Err(MarketplaceError::InvalidVaultAccount.into());
+        }
+
+        check_token_account(
+            legacy_vault_token,
+            legacy_vault.key,
+            mint.key,
+            MarketplaceError::InvalidVaultTokenAccount,
+        )?;
+        if legacy_vault_token.owner != token_program.key {
+            return Err(MarketplaceError::VaultTokenAccountNotInitialized.into());
+        }
+        Vault::Stake.check_vault(program_id, pool_info, &pool, vault)?;
+        Vault::Reward.check_vault(program_id, pool_info, &pool, reward_vault)?;
+        for (vault, vault_token) in [(vault, vault_token), (reward_vault, reward_vault_token)] {
+            check_token_account(
+                vault_token,
+                vault.key,
+                mint.key,
+                MarketplaceError::InvalidVaultTokenAccount,
+            )?;
+        }
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+            legacy_stake_info,
+            legacy_stake_data,
+            legacy_vault,
+            legacy_vault_bump,
+            legacy_vault_token,
+            mint,
+            vault,
+            vault_token,
+            reward_vault,
+            reward_vault_token,
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+            config,
+        })
+    }
+}
+
+/// Accounts of `ReleaseExpiredLock`, which anyone may send
+struct ReleaseLockAccounts<'a, 'info> {
+    staker: &'a AccountInfo<'info>,
+    pool_info: &'a AccountInfo<'info>,
+    pool: PoolData,
+    stake_info: &'a AccountInfo<'info>,
+    stake_data: StakeData,
+}
+
+impl<'a, 'info> ReleaseLockAccounts<'a, 'info> {
+    fn parse(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+
+        let pool = load_pool(program_id, pool_info)?;
+        let stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+
+        Ok(Self {
+            staker,
+            pool_info,
+            pool,
+            stake_info,
+            stake_data,
+        })
+    }
+}
+
+/// Accounts of `GetPendingReward`, which writes nothing and needs no signer
+struct PendingRewardAccounts {
+    pool: PoolData,
+    stake_data: StakeData,
+}
+
+impl PendingRewardAccounts {
+    fn parse(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let staker = next_account_info(accounts_iter)?;
+        let pool_info = next_account_info(accounts_iter)?;
+        let stake_info = next_account_info(accounts_iter)?;
+
+        let pool = load_pool(program_id, pool_info)?;
+        let stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+
+        Ok(Self { pool, stake_data })
+    }
+}
+
+fn process_generate_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let GenerateVaultAccounts {
+        admin,
+        pool_info,
+        pool,
+        vault,
+        system_program,
+        rent_info,
+    } = GenerateVaultAccounts::parse(program_id, accounts)?;
+
+    create_pda_account(
+        program_id,
+        admin,
+        vault,
+        system_program,
+        rent_info,
+        VaultData::LEN,
+        &[
+            VAULT_PREFIX.as_bytes(),
+            &pool_info.key.to_bytes(),
+            &[pool.vault_bump],
+        ],
+    )?;
+
+    let vault_data = VaultData {
+        pool: *pool_info.key,
+    };
+    vault_data.pack(&mut vault.data.borrow_mut())?;
+
+    MarketplaceEvent::VaultGenerated {
+        pool: *pool_info.key,
+        vault: *vault.key,
+    }
+    .emit()
+}
+
+fn process_stake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    lock_tier: Option<u8>,
+) -> ProgramResult {
+    let VaultAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        mint,
+        vault,
+        vault_token,
+        staker_token,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent_info,
+        config,
+        ..
+    } = VaultAccounts::parse(program_id, accounts, Vault::Stake)?;
+    config.check_not_paused(PAUSE_STAKE)?;
+
+    let lock_tier = match lock_tier {
+        Some(index) => Some(pool.lock_tier(index)?),
+        None => None,
+    };
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    pool.update(timestamp)?;
+
+    // initialize stake info PDA if not exist
+    // update generated reward and new staking amount if exist
+    let lock_end = if stake_info.owner != program_id {
+        let mut stake_data = create_stake_account(
+            program_id,
+            staker,
+            pool_info,
+            stake_info,
+            system_program,
+            rent_info,
+            timestamp,
+        )?;
+        stake_data.amount = amount;
+        if let Some(tier) = &lock_tier {
+            stake_data.apply_lock(tier, timestamp)?;
+        }
+        stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+        pool.replace_weight(0, stake_data.weight()?)?;
+        stake_data.pack(&mut stake_info.data.borrow_mut())?;
+        stake_data.lock_end
+    } else {
+        let mut stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+
+        stake_data.checkpoint(pool.acc_reward_per_share)?;
+        let old_weight = stake_data.weight()?;
+        stake_data.release_expired_lock(timestamp);
+        if let Some(tier) = &lock_tier {
+            stake_data.apply_lock(tier, timestamp)?;
+        }
+        stake_data.amount = stake_data
+            .amount
+            .checked_add(amount)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+        pool.replace_weight(old_weight, stake_data.weight()?)?;
+        stake_data.pack(&mut stake_info.data.borrow_mut())?;
+        stake_data.lock_end
+    };
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    // create vault ata
+    create_token_account_if_missing(
+        staker,
+        vault_token,
+        vault,
+        mint,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent_info,
+    )?;
+
+    // transfer staking token to vault
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            staker_token.key,
+            vault_token.key,
+            staker.key,
+            &[],
+            amount,
+        )?,
+        &[
+            vault_token.clone(),
+            staker_token.clone(),
+            staker.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    MarketplaceEvent::Staked {
+        pool: *pool_info.key,
+        staker: *staker.key,
+        amount,
+        lock_end,
+    }
+    .emit()
+}
+
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    early_exit: bool,
+) -> ProgramResult {
+    let mut accounts = VaultAccounts::parse(program_id, accounts, Vault::Stake)?;
+    accounts.config.check_not_paused(PAUSE_WITHDRAW)?;
+    let pool = &mut accounts.pool;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(
+        program_id,
+        accounts.pool_info,
+        accounts.staker,
+        accounts.stake_info,
+    )?;
+    if amount > stake_data.amount {
+        return Err(MarketplaceError::InsufficientStake.into());
+    }
+
+    // principal under lock only leaves through the penalized early exit,
+    // unlocked principal through the cooldown queue when the pool has one
+    let penalty = if timestamp < stake_data.lock_end {
+        if !early_exit {
+            return Err(MarketplaceError::StakeLocked.into());
+        }
+        (amount as u128 * pool.early_withdraw_penalty_bps as u128 / BASIS_POINTS as u128) as u64
+    } else if pool.unstake_cooldown > 0 {
+        return Err(MarketplaceError::UnstakeCooldownRequired.into());
+    } else {
+        0
+    };
+
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    stake_data.amount = stake_data
+        .amount
+        .checked_sub(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    if stake_data.amount == 0 {
+        stake_data.lock_end = 0;
+        stake_data.reward_multiplier_bps = BASIS_POINTS;
+    }
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut accounts.stake_info.data.borrow_mut())?;
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_sub(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut accounts.pool_info.data.borrow_mut())?;
+
+    accounts.create_staker_token_account()?;
+    accounts.burn_penalty(penalty)?;
+    accounts.transfer_to_staker(Vault::Stake, amount - penalty)?;
+
+    MarketplaceEvent::Withdrawn {
+        pool: *accounts.pool_info.key,
+        staker: *accounts.staker.key,
+        amount,
+        penalty,
+    }
+    .emit()
+}
+
+fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut accounts = VaultAccounts::parse(program_id, accounts, Vault::Reward)?;
+    accounts.config.check_not_paused(PAUSE_CLAIM)?;
+    if accounts.vault_token.owner != accounts.token_program.key {
+        return Err(MarketplaceError::VaultTokenAccountNotInitialized.into());
+    }
+    let pool = &mut accounts.pool;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(
+        program_id,
+        accounts.pool_info,
+        accounts.staker,
+        accounts.stake_info,
+    )?;
+
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    let reward_amount = stake_data.remained_reward;
+
+    let vault_balance =
+        spl_token::state::Account::unpack(&accounts.vault_token.data.borrow())?.amount;
+    if reward_amount > vault_balance {
+        return Err(MarketplaceError::RewardVaultUnderfunded.into());
+    }
+
+    stake_data.remained_reward = 0;
+    stake_data.last_claim_time = timestamp;
+    stake_data.pack(&mut accounts.stake_info.data.borrow_mut())?;
+    pool.pack(&mut accounts.pool_info.data.borrow_mut())?;
+
+    accounts.create_staker_token_account()?;
+    accounts.transfer_to_staker(Vault::Reward, reward_amount)?;
+
+    MarketplaceEvent::Claimed {
+        pool: *accounts.pool_info.key,
+        staker: *accounts.staker.key,
+        amount: reward_amount,
+    }
+    .emit()
+}
+
+fn process_initialize(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let InitializeAccounts {
+        admin,
+        config_info,
+        config_bump,
+        system_program,
+        rent_info,
+    } = InitializeAccounts::parse(program_id, accounts)?;
+
+    create_pda_account(
+        program_id,
+        admin,
+        config_info,
+        system_program,
+        rent_info,
+        ConfigData::LEN,
+        &[CONFIG_PREFIX.as_bytes(), &[config_bump]],
+    )?;
+
+    let config = ConfigData {
+        admin: *admin.key,
+        bump: config_bump,
+        pending_admin: Pubkey::default(),
+        guardian: Pubkey::default(),
+        paused: 0,
+    };
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::Initialized { admin: *admin.key }.emit()
+}
+
+fn process_create_pool(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pool_id: u64,
+    reward_rate: u64,
+) -> ProgramResult {
+    let CreatePoolAccounts {
+        admin,
+        pool_info,
+        pool_bump,
+        stake_mint,
+        reward_mint,
+        system_program,
+        rent_info,
+    } = CreatePoolAccounts::parse(program_id, accounts, pool_id)?;
+
+    create_pda_account(
+        program_id,
+        admin,
+        pool_info,
+        system_program,
+        rent_info,
+        PoolData::LEN,
+        &[POOL_PREFIX.as_bytes(), &pool_id.to_le_bytes(), &[pool_bump]],
+    )?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    let mut rate_history = [RateChange::default(); RATE_HISTORY_LEN];
+    rate_history[0] = RateChange {
+        start_time: timestamp,
+        reward_rate,
+    };
+    let pool = PoolData {
+        pool_id,
+        stake_token_mint: *stake_mint.key,
+        reward_token_mint: *reward_mint.key,
+        reward_rate,
+        reward_budget: 0,
+        total_staked: 0,
+        total_weight: 0,
+        acc_reward_per_share: 0,
+        last_update_time: timestamp,
+        lock_tiers: [LockTier::default(); MAX_LOCK_TIERS],
+        early_withdraw_penalty_bps: 0,
+        unstake_cooldown: 0,
+        bump: pool_bump,
+        vault_bump: find_vault_address(program_id, pool_info.key).1,
+        reward_vault_bump: find_reward_vault_address(program_id, pool_info.key).1,
+        rate_history,
+        emission_schedule: EmissionSchedule::default(),
+    };
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::PoolCreated {
+        pool: *pool_info.key,
+        pool_id,
+        stake_token_mint: pool.stake_token_mint,
+        reward_token_mint: pool.reward_token_mint,
+        reward_rate,
+    }
+    .emit()
+}
+
+fn process_fund_rewards(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let FundRewardsAccounts {
+        funder,
+        pool_info,
+        mut pool,
+        mint,
+        vault,
+        vault_token,
+        funder_token,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent_info,
+    } = FundRewardsAccounts::parse(program_id, accounts)?;
+
+    // settle emissions up to now so the new funds only pay for future time
+    pool.update(Clock::get()?.unix_timestamp)?;
+    pool.reward_budget = pool
+        .reward_budget
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    // create reward vault ata
+    create_token_account_if_missing(
+        funder,
+        vault_token,
+        vault,
+        mint,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent_info,
+    )?;
+
+    // transfer reward token to reward vault
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            funder_token.key,
+            vault_token.key,
+            funder.key,
+            &[],
+            amount,
+        )?,
+        &[
+            vault_token.clone(),
+            funder_token.clone(),
+            funder.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    MarketplaceEvent::RewardsFunded {
+        pool: *pool_info.key,
+        funder: *funder.key,
+        amount,
+    }
+    .emit()
+}
+
+fn process_set_lock_tiers(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lock_tiers: Vec<LockTier>,
+    early_withdraw_penalty_bps: u64,
+) -> ProgramResult {
+    let AdminPoolAccounts {
+        pool_info,
+        mut pool,
+    } = AdminPoolAccounts::parse(program_id, accounts)?;
+
+    if lock_tiers.len() > MAX_LOCK_TIERS
+        || early_withdraw_penalty_bps > BASIS_POINTS
+        || lock_tiers
+            .iter()
+            .any(|tier| tier.duration <= 0 || tier.reward_multiplier_bps < BASIS_POINTS)
+    {
+        return Err(MarketplaceError::InvalidLockTierConfig.into());
+    }
+
+    // existing locks keep the multiplier they were created with
+    pool.lock_tiers = [LockTier::default(); MAX_LOCK_TIERS];
+    pool.lock_tiers[..lock_tiers.len()].copy_from_slice(&lock_tiers);
+    pool.early_withdraw_penalty_bps = early_withdraw_penalty_bps;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::LockTiersSet {
+        pool: *pool_info.key,
+        lock_tiers,
+        early_withdraw_penalty_bps,
+    }
+    .emit()
+}
+
+fn process_request_unstake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let UnstakeAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        config,
+    } = UnstakeAccounts::parse(program_id, accounts)?;
+    config.check_not_paused(PAUSE_WITHDRAW)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+    if amount > stake_data.amount {
+        return Err(MarketplaceError::InsufficientStake.into());
+    }
+    if timestamp < stake_data.lock_end {
+        return Err(MarketplaceError::StakeLocked.into());
+    }
+
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    stake_data.amount = stake_data
+        .amount
+        .checked_sub(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    stake_data.pending_unstake = stake_data
+        .pending_unstake
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    // a new request restarts the cooldown of the whole pending amount
+    stake_data.unstake_requested_at = timestamp;
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_sub(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::UnstakeRequested {
+        pool: *pool_info.key,
+        staker: *staker.key,
+        amount,
+    }
+    .emit()
+}
+
+fn process_complete_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts = VaultAccounts::parse(program_id, accounts, Vault::Stake)?;
+    accounts.config.check_not_paused(PAUSE_WITHDRAW)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(
+        program_id,
+        accounts.pool_info,
+        accounts.staker,
+        accounts.stake_info,
+    )?;
+    if stake_data.pending_unstake == 0 {
+        return Err(MarketplaceError::NoPendingUnstake.into());
+    }
+    let cooldown_end = stake_data
+        .unstake_requested_at
+        .checked_add(accounts.pool.unstake_cooldown)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    if timestamp < cooldown_end {
+        return Err(MarketplaceError::CooldownNotElapsed.into());
+    }
+
+    let amount = stake_data.pending_unstake;
+    stake_data.pending_unstake = 0;
+    stake_data.unstake_requested_at = 0;
+    stake_data.pack(&mut accounts.stake_info.data.borrow_mut())?;
+
+    accounts.create_staker_token_account()?;
+    accounts.transfer_to_staker(Vault::Stake, amount)?;
+
+    MarketplaceEvent::UnstakeCompleted {
+        pool: *accounts.pool_info.key,
+        staker: *accounts.staker.key,
+        amount,
+    }
+    .emit()
+}
+
+fn process_cancel_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let UnstakeAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        config,
+    } = UnstakeAccounts::parse(program_id, accounts)?;
+    // re-staking the queue adds weight, as `Stake` does
+    config.check_not_paused(PAUSE_STAKE)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+    if stake_data.pending_unstake == 0 {
+        return Err(MarketplaceError::NoPendingUnstake.into());
+    }
+
+    let amount = stake_data.pending_unstake;
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    stake_data.amount = stake_data
+        .amount
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    stake_data.pending_unstake = 0;
+    stake_data.unstake_requested_at = 0;
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::UnstakeCancelled {
+        pool: *pool_info.key,
+        staker: *staker.key,
+        amount,
+    }
+    .emit()
+}
+
+fn process_set_unstake_cooldown(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    unstake_cooldown: i64,
+) -> ProgramResult {
+    let AdminPoolAccounts {
+        pool_info,
+        mut pool,
+    } = AdminPoolAccounts::parse(program_id, accounts)?;
+
+    if unstake_cooldown < 0 {
+        return Err(MarketplaceError::InvalidUnstakeCooldown.into());
+    }
+
+    // pending requests complete against the new cooldown
+    pool.unstake_cooldown = unstake_cooldown;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::UnstakeCooldownSet {
+        pool: *pool_info.key,
+        unstake_cooldown,
+    }
+    .emit()
+}
+
+fn process_close_stake_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let PositionAccounts {
+        staker,
+        pool_info,
+        pool,
+        stake_info,
+    } = PositionAccounts::parse(program_id, &mut accounts.iter())?;
+
+    let stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+    // without principal the weight is zero, so nothing accrues past remained_reward
+    if stake_data.amount != 0
+        || stake_data.pending_unstake != 0
+        || stake_data.remained_reward != 0
+        || stake_data.pending_reward(pool.acc_reward_per_share)? != 0
+    {
+        return Err(MarketplaceError::StakeAccountNotEmpty.into());
+    }
+
+    // hand the account back to the system program so the next stake recreates it
+    close_account(stake_info, staker)?;
+
+    MarketplaceEvent::StakeAccountClosed {
+        pool: *pool_info.key,
+        staker: *staker.key,
+    }
+    .emit()
+}
+
+fn process_migrate_stake_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let MigrateStakeAccounts {
+        staker,
+        pool_info,
+        stake_info,
+        stake_data,
+        system_program,
+        rent_info,
+    } = MigrateStakeAccounts::parse(program_id, accounts)?;
+    let rent = &Rent::from_account_info(rent_info)?;
+
+    // top up rent for the added bytes before growing the account
+    let required_lamports = rent
+        .minimum_balance(StakeData::LEN)
+        .saturating_sub(stake_info.lamports());
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(staker.key, stake_info.key, required_lamports),
+            &[staker.clone(), stake_info.clone(), system_program.clone()],
+        )?;
+    }
+    stake_info.realloc(StakeData::LEN, false)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+
+    MarketplaceEvent::StakeAccountMigrated {
+        pool: *pool_info.key,
+        staker: *staker.key,
+    }
+    .emit()
+}
+
+fn process_migrate_legacy_stake_account(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let MigrateLegacyStakeAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        legacy_stake_info,
+        legacy_stake_data,
+        legacy_vault,
+        legacy_vault_bump,
+        legacy_vault_token,
+        mint,
+        vault,
+        vault_token,
+        reward_vault,
+        reward_vault_token,
+        token_program,
+        associated_token_program,
+        system_program,
+        rent_info,
+        config,
+    } = MigrateLegacyStakeAccounts::parse(program_id, accounts)?;
+    // the moved principal adds weight, as `Stake` does
+    config.check_not_paused(PAUSE_STAKE)?;
+
+    // the original program paid rewards out of the same vault as the principal,
+    // so its checkpointed reward only moves as far as the vault still covers it
+    let amount = legacy_stake_data.amount;
+    let legacy_balance =
+        spl_token::state::Account::unpack(&legacy_vault_token.data.borrow())?.amount;
+    let reward = legacy_stake_data
+        .remained_reward
+        .min(legacy_balance.saturating_sub(amount));
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    pool.update(timestamp)?;
+
+    let (mut stake_data, old_weight) = if stake_info.owner != program_id {
+        let stake_data = create_stake_account(
+            program_id,
+            staker,
+            pool_info,
+            stake_info,
+            system_program,
+            rent_info,
+            timestamp,
+        )?;
+        (stake_data, 0)
+    } else {
+        let mut stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+        stake_data.checkpoint(pool.acc_reward_per_share)?;
+        let old_weight = stake_data.weight()?;
+        stake_data.release_expired_lock(timestamp);
+        (stake_data, old_weight)
+    };
+    stake_data.amount = stake_data
+        .amount
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    stake_data.remained_reward = stake_data
+        .remained_reward
+        .checked_add(reward)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_add(amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    for (vault, destination, amount) in [
+        (vault, vault_token, amount),
+        (reward_vault, reward_vault_token, reward),
+    ] {
+        create_token_account_if_missing(
+            staker,
+            destination,
+            vault,
+            mint,
+            token_program,
+            associated_token_program,
+            system_program,
+            rent_info,
+        )?;
+        transfer_from_legacy_vault(
+            token_program,
+            legacy_vault,
+            legacy_vault_bump,
+            legacy_vault_token,
+            destination,
+            amount,
+        )?;
+    }
+    close_account(legacy_stake_info, staker)?;
+
+    MarketplaceEvent::StakeAccountMigrated {
+        pool: *pool_info.key,
+        staker: *staker.key,
+    }
+    .emit()
+}
+
+fn process_get_pending_reward(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let PendingRewardAccounts {
+        mut pool,
+        stake_data,
+    } = PendingRewardAccounts::parse(program_id, accounts)?;
+
+    // same accrual as `Claim`, applied to copies that are never written back
+    pool.update(Clock::get()?.unix_timestamp)?;
+    let reward = stake_data
+        .remained_reward
+        .checked_add(stake_data.pending_reward(pool.acc_reward_per_share)?)
+        .ok_or(MarketplaceError::MathOverflow)?;
+
+    set_return_data(&reward.to_le_bytes());
+    Ok(())
+}
+
+fn process_compound(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let CompoundAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        vault_token,
+        reward_vault,
+        reward_vault_token,
+        token_program,
+        config,
+    } = CompoundAccounts::parse(program_id, accounts)?;
+    // compounding both claims and stakes
+    config.check_not_paused(PAUSE_STAKE | PAUSE_CLAIM)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    let mut stake_data = load_stake(program_id, pool_info, staker, stake_info)?;
+
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    let reward_amount = stake_data.remained_reward;
+
+    let vault_balance =
+        spl_token::state::Account::unpack(&reward_vault_token.data.borrow())?.amount;
+    if reward_amount > vault_balance {
+        return Err(MarketplaceError::RewardVaultUnderfunded.into());
+    }
+
+    // the reward joins the principal, and any lock still running on it
+    stake_data.remained_reward = 0;
+    stake_data.last_claim_time = timestamp;
+    stake_data.amount = stake_data
+        .amount
+        .checked_add(reward_amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+
+    pool.total_staked = pool
+        .total_staked
+        .checked_add(reward_amount)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            reward_vault_token.key,
+            vault_token.key,
+            reward_vault.key,
+            &[],
+            reward_amount,
+        )?,
+        &[
+            reward_vault_token.clone(),
+            vault_token.clone(),
+            reward_vault.clone(),
+            token_program.clone(),
+        ],
+        &[&[
+            REWARD_VAULT_PREFIX.as_bytes(),
+            &pool_info.key.to_bytes(),
+            &[pool.reward_vault_bump],
+        ]],
+    )?;
+
+    MarketplaceEvent::Compounded {
+        pool: *pool_info.key,
+        staker: *staker.key,
+        amount: reward_amount,
+    }
+    .emit()
+}
+
+fn process_propose_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let ConfigAccounts {
+        authority: admin,
+        config_info,
+        mut config,
+    } = ConfigAccounts::parse(program_id, accounts)?;
+    check_admin(admin, &config)?;
+
+    config.pending_admin = new_admin;
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::AdminProposed {
+        admin: *admin.key,
+        new_admin,
+    }
+    .emit()
+}
+
+fn process_accept_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let ConfigAccounts {
+        authority: new_admin,
+        config_info,
+        mut config,
+    } = ConfigAccounts::parse(program_id, accounts)?;
+    check_signer(new_admin)?;
+    if config.pending_admin == Pubkey::default() || *new_admin.key != config.pending_admin {
+        return Err(MarketplaceError::NotPendingAdmin.into());
+    }
+
+    let previous_admin = config.admin;
+    config.admin = config.pending_admin;
+    config.pending_admin = Pubkey::default();
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::AdminAccepted {
+        previous_admin,
+        admin: config.admin,
+    }
+    .emit()
+}
+
+fn process_renounce_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let ConfigAccounts {
+        authority: admin,
+        config_info,
+        mut config,
+    } = ConfigAccounts::parse(program_id, accounts)?;
+    check_admin(admin, &config)?;
+
+    // a pending proposal must not revive the admin
+    config.admin = Pubkey::default();
+    config.pending_admin = Pubkey::default();
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::AdminRenounced { admin: *admin.key }.emit()
+}
+
+fn process_set_guardian(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    guardian: Pubkey,
+) -> ProgramResult {
+    let ConfigAccounts {
+        authority: admin,
+        config_info,
+        mut config,
+    } = ConfigAccounts::parse(program_id, accounts)?;
+    check_admin(admin, &config)?;
+
+    config.guardian = guardian;
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::GuardianSet { guardian }.emit()
+}
+
+fn process_set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: u8) -> ProgramResult {
+    let ConfigAccounts {
+        authority,
+        config_info,
+        mut config,
+    } = ConfigAccounts::parse(program_id, accounts)?;
+    if paused & !PAUSE_ALL != 0 {
+        return Err(MarketplaceError::InvalidPauseFlags.into());
+    }
+    // the guardian keeps working after the admin is renounced, but only to pause
+    let is_guardian = config.guardian != Pubkey::default() && *authority.key == config.guardian;
+    if is_guardian && *authority.key != config.admin {
+        check_signer(authority)?;
+        if paused & config.paused != config.paused {
+            return Err(MarketplaceError::GuardianCannotUnpause.into());
+        }
+    } else {
+        check_admin(authority, &config)?;
+    }
+
+    config.paused = paused;
+    config.pack(&mut config_info.data.borrow_mut())?;
+
+    MarketplaceEvent::PausedSet {
+        authority: *authority.key,
+        paused,
+    }
+    .emit()
+}
+
+fn process_emergency_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // no pause check: this is the way out while the program is paused
+    let mut accounts = VaultAccounts::parse(program_id, accounts, Vault::Stake)?;
+    let pool = &mut accounts.pool;
+
+    let mut stake_data = load_stake(
+        program_id,
+        accounts.pool_info,
+        accounts.staker,
+        accounts.stake_info,
+    )?;
+    let amount = stake_data
+        .amount
+        .checked_add(stake_data.pending_unstake)
+        .ok_or(MarketplaceError::MathOverflow)?;
+    if amount == 0 {
+        return Err(MarketplaceError::InsufficientStake.into());
+    }
+
+    // the reward already taken out of the budget for this position goes back
+    // to it. The accumulator is not updated, so the emission since the last
+    // update goes to the remaining stakers. The bookkeeping saturates rather
+    // than fail, nothing in the reward state may hold the principal back.
+    let forfeited_reward = stake_data.remained_reward.saturating_add(
+        stake_data
+            .pending_reward(pool.acc_reward_per_share)
+            .unwrap_or(0),
+    );
+    pool.reward_budget = pool.reward_budget.saturating_add(forfeited_reward);
+    pool.total_weight = pool
+        .total_weight
+        .saturating_sub(stake_data.weight().unwrap_or(u64::MAX));
+    pool.total_staked = pool.total_staked.saturating_sub(stake_data.amount);
+    pool.pack(&mut accounts.pool_info.data.borrow_mut())?;
+
+    stake_data.amount = 0;
+    stake_data.reward_debt = 0;
+    stake_data.remained_reward = 0;
+    stake_data.lock_end = 0;
+    stake_data.reward_multiplier_bps = BASIS_POINTS;
+    stake_data.pending_unstake = 0;
+    stake_data.unstake_requested_at = 0;
+    stake_data.pack(&mut accounts.stake_info.data.borrow_mut())?;
+
+    accounts.create_staker_token_account()?;
+    accounts.transfer_to_staker(Vault::Stake, amount)?;
+
+    MarketplaceEvent::EmergencyWithdrawn {
+        pool: *accounts.pool_info.key,
+        staker: *accounts.staker.key,
+        amount,
+        forfeited_reward,
+    }
+    .emit()
+}
+
+fn process_set_reward_rate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    reward_rate: u64,
+) -> ProgramResult {
+    let AdminPoolAccounts {
+        pool_info,
+        mut pool,
+    } = AdminPoolAccounts::parse(program_id, accounts)?;
+
+    let previous_rate = pool.reward_rate;
+    pool.set_reward_rate(reward_rate, Clock::get()?.unix_timestamp)?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::RewardRateSet {
+        pool: *pool_info.key,
+        previous_rate,
+        reward_rate,
+        start_time: pool.last_update_time,
+    }
+    .emit()
+}
+
+fn process_set_emission_schedule(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    start_time: i64,
+    end_time: i64,
+    halving_period: i64,
+    rate_steps: Vec<RateChange>,
+) -> ProgramResult {
+    let AdminPoolAccounts {
+        pool_info,
+        mut pool,
+    } = AdminPoolAccounts::parse(program_id, accounts)?;
+
+    if start_time < 0
+        || end_time < 0
+        || (end_time > 0 && end_time <= start_time)
+        || halving_period < 0
+        || (halving_period > 0 && start_time == 0)
+        || rate_steps.len() > MAX_RATE_STEPS
+        || rate_steps.iter().any(|step| step.start_time <= 0)
+        || rate_steps
+            .windows(2)
+            .any(|steps| steps[0].start_time >= steps[1].start_time)
+    {
+        return Err(MarketplaceError::InvalidEmissionSchedule.into());
+    }
+
+    // close the elapsed period under the schedule it ran with
+    pool.update(Clock::get()?.unix_timestamp)?;
+    let mut schedule = EmissionSchedule {
+        start_time,
+        end_time,
+        halving_period,
+        rate_steps: [RateChange::default(); MAX_RATE_STEPS],
+    };
+    schedule.rate_steps[..rate_steps.len()].copy_from_slice(&rate_steps);
+    pool.emission_schedule = schedule;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::EmissionScheduleSet {
+        pool: *pool_info.key,
+        start_time,
+        end_time,
+        halving_period,
+        rate_steps,
+    }
+    .emit()
+}
+
+fn process_release_expired_lock(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let ReleaseLockAccounts {
+        staker,
+        pool_info,
+        mut pool,
+        stake_info,
+        mut stake_data,
+    } = ReleaseLockAccounts::parse(program_id, accounts)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+
+    if stake_data.lock_end == 0 {
+        return Err(MarketplaceError::NoExpiredLock.into());
+    }
+    if timestamp < stake_data.lock_end {
+        return Err(MarketplaceError::StakeLocked.into());
+    }
+
+    // the boost is paid up to now, no later
+    pool.update(timestamp)?;
+    stake_data.checkpoint(pool.acc_reward_per_share)?;
+    let old_weight = stake_data.weight()?;
+    stake_data.release_expired_lock(timestamp);
+    stake_data.reset_reward_debt(pool.acc_reward_per_share)?;
+    pool.replace_weight(old_weight, stake_data.weight()?)?;
+    stake_data.pack(&mut stake_info.data.borrow_mut())?;
+    pool.pack(&mut pool_info.data.borrow_mut())?;
+
+    MarketplaceEvent::LockReleased {
+        pool: *pool_info.key,
+        staker: *staker.key,
+    }
+    .emit()
+}
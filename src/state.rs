@@ -0,0 +1,940 @@
+//! Program account state
+
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::convert::TryFrom;
+
+use crate::error::MarketplaceError;
+
+/// Scale of `PoolData::acc_reward_per_share`
+pub const ACC_REWARD_PRECISION: u128 = 1_000_000_000_000;
+/// 100% in basis points, also the multiplier of unlocked stake
+pub const BASIS_POINTS: u64 = 10_000;
+/// Number of lock tiers a pool can configure
+pub const MAX_LOCK_TIERS: usize = 4;
+/// Number of reward rates a pool remembers, the current one included
+pub const RATE_HISTORY_LEN: usize = 8;
+/// Number of rate steps an emission schedule can hold
+pub const MAX_RATE_STEPS: usize = 8;
+/// Seconds in the 365-day year used to quote a per-second `reward_rate` yearly
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+/// Pause flag stopping `Stake`, `Compound` and `CancelUnstake`
+pub const PAUSE_STAKE: u8 = 1 << 0;
+/// Pause flag stopping `Withdraw`, `EarlyWithdraw`, `RequestUnstake` and
+/// `CompleteUnstake`
+pub const PAUSE_WITHDRAW: u8 = 1 << 1;
+/// Pause flag stopping `Claim` and `Compound`
+pub const PAUSE_CLAIM: u8 = 1 << 2;
+/// Every pause flag
+pub const PAUSE_ALL: u8 = PAUSE_STAKE | PAUSE_WITHDRAW | PAUSE_CLAIM;
+
+/// A lock duration stakers can opt into for boosted rewards
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct LockTier {
+    /// Lock duration in seconds, zero for an unused tier
+    pub duration: i64,
+    /// Reward multiplier in basis points, at least `BASIS_POINTS`
+    pub reward_multiplier_bps: u64,
+}
+
+/// A reward rate of a pool and the time it took effect
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct RateChange {
+    /// Unix timestamp from which the rate applied, zero for an unused entry
+    pub start_time: i64,
+    /// Reward token base units emitted per second
+    pub reward_rate: u64,
+}
+
+/// When and how fast a pool emits rewards. The default schedule emits
+/// `reward_rate` per second forever.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct EmissionSchedule {
+    /// Unix timestamp emission starts at, zero to start right away
+    pub start_time: i64,
+    /// Unix timestamp emission stops at, zero to never stop
+    pub end_time: i64,
+    /// Seconds after which the rate halves, counted from `start_time`, zero
+    /// to never halve
+    pub halving_period: i64,
+    /// Rates replacing the pool's `reward_rate` from their start time on,
+    /// sorted by start time, unused entries last
+    pub rate_steps: [RateChange; MAX_RATE_STEPS],
+}
+
+impl EmissionSchedule {
+    // rate per second at `timestamp`, within the emission window, and the
+    // time it next changes, `i64::MAX` if never
+    fn rate_at(&self, base_rate: u64, timestamp: i64) -> (u64, i64) {
+        let mut rate = base_rate;
+        let mut next_change = i64::MAX;
+        for step in self.rate_steps.iter().filter(|step| step.start_time > 0) {
+            if step.start_time <= timestamp {
+                rate = step.reward_rate;
+            } else {
+                next_change = step.start_time;
+                break;
+            }
+        }
+        if self.halving_period > 0 {
+            let halvings = (timestamp - self.start_time) / self.halving_period;
+            if halvings >= u64::BITS as i64 {
+                rate = 0;
+            } else {
+                rate >>= halvings;
+                let next_halving = (halvings + 1)
+                    .checked_mul(self.halving_period)
+                    .and_then(|offset| self.start_time.checked_add(offset))
+                    .unwrap_or(i64::MAX);
+                next_change = next_change.min(next_halving);
+            }
+        }
+        (rate, next_change)
+    }
+}
+
+/// Size of the type discriminator leading every program-owned account
+pub(crate) const DISCRIMINATOR_SIZE: usize = 8;
+/// Current stake account layout, stored after the discriminator
+pub(crate) const STAKE_DATA_VERSION: u8 = 2;
+/// Body size of stake accounts in version 1, which predates the stored bump
+pub(crate) const STAKE_DATA_V1_SIZE: usize = 136;
+/// Size of the headerless stake accounts of the original program, read as version 0
+pub(crate) const LEGACY_STAKE_DATA_SIZE: usize = 56;
+
+/// Data of a program-owned account, stored behind a type discriminator
+pub trait AccountData: BorshDeserialize + BorshSerialize {
+    const DISCRIMINATOR: [u8; DISCRIMINATOR_SIZE];
+    /// Allocated size of the account, discriminator included
+    const LEN: usize;
+    /// Error for an account the program does not own
+    const NOT_INITIALIZED: MarketplaceError;
+    /// Error for data that does not decode as this type
+    const INVALID_DATA: MarketplaceError;
+
+    fn unpack(data: &[u8]) -> Result<Self, MarketplaceError> {
+        if data.get(..DISCRIMINATOR_SIZE) != Some(&Self::DISCRIMINATOR[..]) {
+            return Err(MarketplaceError::InvalidAccountDiscriminator);
+        }
+        Self::try_from_slice(&data[DISCRIMINATOR_SIZE..]).map_err(|_| Self::INVALID_DATA)
+    }
+
+    fn pack(&self, data: &mut [u8]) -> ProgramResult {
+        if data.len() < DISCRIMINATOR_SIZE {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let (discriminator, body) = data.split_at_mut(DISCRIMINATOR_SIZE);
+        discriminator.copy_from_slice(&Self::DISCRIMINATOR);
+        self.serialize(&mut &mut body[..])?;
+        Ok(())
+    }
+}
+
+// checks the owner and discriminator of an account and decodes its data
+pub(crate) fn load_account<T: AccountData>(
+    program_id: &Pubkey,
+    info: &AccountInfo,
+) -> Result<T, ProgramError> {
+    if info.owner != program_id {
+        return Err(T::NOT_INITIALIZED.into());
+    }
+    Ok(T::unpack(&info.data.borrow())?)
+}
+
+/// Position of a staker in a pool, stored in the stake PDA
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct StakeData {
+    pub pool: Pubkey,               // 32
+    pub staker: Pubkey,             // 32
+    pub amount: u64,                // 8
+    pub reward_debt: u128,          // 16
+    pub remained_reward: u64,       // 8
+    pub last_claim_time: i64,       // 8
+    pub lock_end: i64,              // 8
+    pub reward_multiplier_bps: u64, // 8
+    pub pending_unstake: u64,       // 8, cooling down, earns no reward
+    pub unstake_requested_at: i64,  // 8
+    pub bump: u8,                   // 1, zero when read from a layout before version 2
+}
+
+// stake account of the original program, kept at `["stake", staker]` outside
+// any pool and written without a discriminator
+#[derive(BorshDeserialize)]
+struct LegacyStakeData {
+    staker: Pubkey,       // 32
+    amount: u64,          // 8
+    remained_reward: u64, // 8
+    last_claim_time: i64, // 8
+}
+
+impl AccountData for StakeData {
+    const DISCRIMINATOR: [u8; DISCRIMINATOR_SIZE] = *b"stake\0\0\0";
+    const LEN: usize = 146; // 8 discriminator + 1 version + 137 data
+    const NOT_INITIALIZED: MarketplaceError = MarketplaceError::StakeAccountNotInitialized;
+    const INVALID_DATA: MarketplaceError = MarketplaceError::InvalidStakeData;
+
+    // reads the current layout as well as the older ones awaiting migration:
+    // version 1 and the headerless version 0 of the original program, whose
+    // position belongs to no pool until `MigrateLegacyStakeAccount` moves it
+    fn unpack(data: &[u8]) -> Result<Self, MarketplaceError> {
+        if data.len() == LEGACY_STAKE_DATA_SIZE {
+            let legacy = LegacyStakeData::try_from_slice(data).map_err(|_| Self::INVALID_DATA)?;
+            return Ok(Self {
+                pool: Pubkey::default(),
+                staker: legacy.staker,
+                amount: legacy.amount,
+                reward_debt: 0,
+                remained_reward: legacy.remained_reward,
+                last_claim_time: legacy.last_claim_time,
+                lock_end: 0,
+                reward_multiplier_bps: BASIS_POINTS,
+                pending_unstake: 0,
+                unstake_requested_at: 0,
+                bump: 0,
+            });
+        }
+        if data.get(..DISCRIMINATOR_SIZE) != Some(&Self::DISCRIMINATOR[..]) {
+            return Err(MarketplaceError::InvalidAccountDiscriminator);
+        }
+        match data[DISCRIMINATOR_SIZE..].split_first() {
+            // version 1 ends before the bump, which then reads as zero
+            Some((1, body)) if body.len() == STAKE_DATA_V1_SIZE => {
+                Self::try_from_slice(&[body, &[0]].concat()).map_err(|_| Self::INVALID_DATA)
+            }
+            Some((&STAKE_DATA_VERSION, body)) => {
+                Self::try_from_slice(body).map_err(|_| Self::INVALID_DATA)
+            }
+            _ => Err(Self::INVALID_DATA),
+        }
+    }
+
+    // writes in the layout the account already has, version 1 accounts keep it
+    // until migrated. Version 0 accounts are never written back.
+    fn pack(&self, data: &mut [u8]) -> ProgramResult {
+        if data.len() <= DISCRIMINATOR_SIZE + 1 {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let bytes = self.try_to_vec()?;
+        let (header, body) = data.split_at_mut(DISCRIMINATOR_SIZE + 1);
+        header[..DISCRIMINATOR_SIZE].copy_from_slice(&Self::DISCRIMINATOR);
+        header[DISCRIMINATOR_SIZE] = match body.len() {
+            STAKE_DATA_V1_SIZE => 1,
+            len if len == bytes.len() => STAKE_DATA_VERSION,
+            _ => return Err(Self::INVALID_DATA.into()),
+        };
+        body.copy_from_slice(&bytes[..body.len()]);
+        Ok(())
+    }
+}
+
+impl StakeData {
+    // amount weighted by the lock multiplier, used for the reward share
+    pub(crate) fn weight(&self) -> Result<u64, MarketplaceError> {
+        let weight =
+            self.amount as u128 * self.reward_multiplier_bps as u128 / BASIS_POINTS as u128;
+        u64::try_from(weight).map_err(|_| MarketplaceError::MathOverflow)
+    }
+
+    // reward accrued since the last checkpoint of this stake account
+    pub(crate) fn pending_reward(
+        &self,
+        acc_reward_per_share: u128,
+    ) -> Result<u64, MarketplaceError> {
+        let pending = accrued_reward(self.weight()?, acc_reward_per_share)?
+            .checked_sub(self.reward_debt)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        u64::try_from(pending).map_err(|_| MarketplaceError::MathOverflow)
+    }
+
+    // moves pending reward into remained_reward and resets the debt to the current weight
+    pub(crate) fn checkpoint(
+        &mut self,
+        acc_reward_per_share: u128,
+    ) -> Result<(), MarketplaceError> {
+        self.remained_reward = self
+            .remained_reward
+            .checked_add(self.pending_reward(acc_reward_per_share)?)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        self.reset_reward_debt(acc_reward_per_share)
+    }
+
+    pub(crate) fn reset_reward_debt(
+        &mut self,
+        acc_reward_per_share: u128,
+    ) -> Result<(), MarketplaceError> {
+        self.reward_debt = accrued_reward(self.weight()?, acc_reward_per_share)?;
+        Ok(())
+    }
+
+    // extends the lock of the whole position, keeping the larger multiplier
+    pub(crate) fn apply_lock(
+        &mut self,
+        tier: &LockTier,
+        timestamp: i64,
+    ) -> Result<(), MarketplaceError> {
+        let lock_end = timestamp
+            .checked_add(tier.duration)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        self.lock_end = self.lock_end.max(lock_end);
+        self.reward_multiplier_bps = self.reward_multiplier_bps.max(tier.reward_multiplier_bps);
+        Ok(())
+    }
+
+    // drops the boost once the lock is over; must follow a checkpoint. Until
+    // the position is touched or `ReleaseExpiredLock` is sent the boost keeps
+    // its weight in the pool.
+    pub(crate) fn release_expired_lock(&mut self, timestamp: i64) {
+        if self.lock_end <= timestamp {
+            self.lock_end = 0;
+            self.reward_multiplier_bps = BASIS_POINTS;
+        }
+    }
+}
+
+// total reward earned by `weight` staked tokens at `acc_reward_per_share`
+//
+// A small early stake can push the accumulator far past what a u128 product
+// with a large weight can hold, so the product is taken in 192 bits and only
+// the quotient has to fit.
+pub(crate) fn accrued_reward(
+    weight: u64,
+    acc_reward_per_share: u128,
+) -> Result<u128, MarketplaceError> {
+    const LIMB: u32 = u64::BITS;
+    let low = weight as u128 * (acc_reward_per_share as u64) as u128;
+    let high = weight as u128 * (acc_reward_per_share >> LIMB);
+    let middle = (low >> LIMB) + (high as u64) as u128;
+    // little-endian limbs of weight * acc_reward_per_share
+    let limbs = [
+        low as u64,
+        middle as u64,
+        ((high >> LIMB) + (middle >> LIMB)) as u64,
+    ];
+
+    // long division by the precision, which fits in a limb
+    let divisor = ACC_REWARD_PRECISION;
+    let mut remainder = 0u128;
+    let mut quotient = [0u64; 3];
+    for (index, limb) in limbs.iter().enumerate().rev() {
+        let dividend = (remainder << LIMB) | *limb as u128;
+        quotient[index] = (dividend / divisor) as u64;
+        remainder = dividend % divisor;
+    }
+    if quotient[2] != 0 {
+        return Err(MarketplaceError::MathOverflow);
+    }
+    Ok((quotient[1] as u128) << LIMB | quotient[0] as u128)
+}
+
+/// Program-wide settings, stored in the config PDA
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct ConfigData {
+    pub admin: Pubkey,         // 32, default once renounced
+    pub bump: u8,              // 1
+    pub pending_admin: Pubkey, // 32, default when no transfer is proposed
+    pub guardian: Pubkey,      // 32, may pause but not unpause, default when unset
+    pub paused: u8,            // 1, `PAUSE_*` flags
+}
+
+impl ConfigData {
+    // fails if any of the `flags` is paused
+    pub(crate) fn check_not_paused(&self, flags: u8) -> Result<(), MarketplaceError> {
+        if self.paused & flags != 0 {
+            return Err(MarketplaceError::Paused);
+        }
+        Ok(())
+    }
+}
+
+impl AccountData for ConfigData {
+    const DISCRIMINATOR: [u8; DISCRIMINATOR_SIZE] = *b"config\0\0";
+    const LEN: usize = 106; // 8 discriminator + 98 data
+    const NOT_INITIALIZED: MarketplaceError = MarketplaceError::ConfigNotInitialized;
+    const INVALID_DATA: MarketplaceError = MarketplaceError::InvalidConfigData;
+}
+
+/// Marker of a generated stake vault PDA
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct VaultData {
+    pub pool: Pubkey, // 32
+}
+
+impl AccountData for VaultData {
+    const DISCRIMINATOR: [u8; DISCRIMINATOR_SIZE] = *b"vault\0\0\0";
+    const LEN: usize = 40; // 8 discriminator + 32 data
+    const NOT_INITIALIZED: MarketplaceError = MarketplaceError::InvalidVaultAccount;
+    const INVALID_DATA: MarketplaceError = MarketplaceError::InvalidVaultAccount;
+}
+
+/// Staking pool settings and reward accumulator, stored in the pool PDA
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct PoolData {
+    pub pool_id: u64,                                 // 8
+    pub stake_token_mint: Pubkey,                     // 32
+    pub reward_token_mint: Pubkey,                    // 32
+    pub reward_rate: u64,                             // 8, reward tokens emitted per second
+    pub reward_budget: u64,                           // 8, reward tokens left to emit
+    pub total_staked: u64,                            // 8
+    pub total_weight: u64,                            // 8, sum of stake weights
+    pub acc_reward_per_share: u128,                   // 16
+    pub last_update_time: i64,                        // 8
+    pub lock_tiers: [LockTier; MAX_LOCK_TIERS],       // 64
+    pub early_withdraw_penalty_bps: u64,              // 8
+    pub unstake_cooldown: i64,                        // 8, seconds, zero allows direct withdraw
+    pub bump: u8,                                     // 1
+    pub vault_bump: u8,                               // 1
+    pub reward_vault_bump: u8,                        // 1
+    pub rate_history: [RateChange; RATE_HISTORY_LEN], // 128, newest first, never read for accrual
+    pub emission_schedule: EmissionSchedule,          // 152
+}
+
+impl AccountData for PoolData {
+    const DISCRIMINATOR: [u8; DISCRIMINATOR_SIZE] = *b"pool\0\0\0\0";
+    const LEN: usize = 499; // 8 discriminator + 491 data
+    const NOT_INITIALIZED: MarketplaceError = MarketplaceError::PoolNotInitialized;
+    const INVALID_DATA: MarketplaceError = MarketplaceError::InvalidPoolData;
+}
+
+impl PoolData {
+    // emits rewards for the time elapsed since the last update, capped by the budget
+    pub(crate) fn update(&mut self, timestamp: i64) -> Result<(), MarketplaceError> {
+        // a clock that went backwards emits nothing
+        if timestamp <= self.last_update_time {
+            return Ok(());
+        }
+        if self.total_weight > 0 {
+            let reward = self
+                .emission(self.last_update_time, timestamp)?
+                .min(self.reward_budget as u128);
+            let reward_per_share = reward
+                .checked_mul(ACC_REWARD_PRECISION)
+                .ok_or(MarketplaceError::MathOverflow)?
+                / self.total_weight as u128;
+            self.acc_reward_per_share = self
+                .acc_reward_per_share
+                .checked_add(reward_per_share)
+                .ok_or(MarketplaceError::MathOverflow)?;
+            // reward <= reward_budget
+            self.reward_budget -= reward as u64;
+        }
+        self.last_update_time = timestamp;
+        Ok(())
+    }
+
+    // rewards the schedule emits between `from` and `to`, integrated over each
+    // window, rate step and halving, saturating once above the budget
+    fn emission(&self, from: i64, to: i64) -> Result<u128, MarketplaceError> {
+        let schedule = &self.emission_schedule;
+        let end = match schedule.end_time {
+            0 => to,
+            end_time => to.min(end_time),
+        };
+        let mut time = from.max(schedule.start_time);
+        let mut emission: u128 = 0;
+        while time < end && emission < self.reward_budget as u128 {
+            let (rate, next_change) = schedule.rate_at(self.reward_rate, time);
+            let segment_end = next_change.min(end);
+            let elapsed = segment_end
+                .checked_sub(time)
+                .ok_or(MarketplaceError::MathOverflow)? as u128;
+            // cannot overflow: both factors fit in 64 bits
+            emission = emission.saturating_add(elapsed * rate as u128);
+            time = segment_end;
+        }
+        Ok(emission)
+    }
+
+    // emits what accrued at the current rate up to `timestamp`, then switches
+    // to `reward_rate` and records it, dropping the oldest rate when full
+    pub(crate) fn set_reward_rate(
+        &mut self,
+        reward_rate: u64,
+        timestamp: i64,
+    ) -> Result<(), MarketplaceError> {
+        self.update(timestamp)?;
+        // a started step sets the rate from then on, the new rate would never apply
+        let step_started = self
+            .emission_schedule
+            .rate_steps
+            .iter()
+            .any(|step| step.start_time > 0 && step.start_time <= self.last_update_time);
+        if step_started {
+            return Err(MarketplaceError::RateStepInForce);
+        }
+        self.reward_rate = reward_rate;
+        self.rate_history.rotate_right(1);
+        self.rate_history[0] = RateChange {
+            start_time: self.last_update_time,
+            reward_rate,
+        };
+        Ok(())
+    }
+
+    // swaps the weight of a stake account in the pool total
+    pub(crate) fn replace_weight(
+        &mut self,
+        old_weight: u64,
+        new_weight: u64,
+    ) -> Result<(), MarketplaceError> {
+        self.total_weight = self
+            .total_weight
+            .checked_sub(old_weight)
+            .and_then(|total| total.checked_add(new_weight))
+            .ok_or(MarketplaceError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub(crate) fn lock_tier(&self, index: u8) -> Result<LockTier, MarketplaceError> {
+        match self.lock_tiers.get(index as usize) {
+            Some(tier) if tier.duration > 0 => Ok(*tier),
+            _ => Err(MarketplaceError::InvalidLockTier),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(reward_rate: u64, reward_budget: u64, total_staked: u64) -> PoolData {
+        PoolData {
+            pool_id: 0,
+            stake_token_mint: Pubkey::new_unique(),
+            reward_token_mint: Pubkey::new_unique(),
+            reward_rate,
+            reward_budget,
+            total_staked,
+            total_weight: total_staked,
+            acc_reward_per_share: 0,
+            last_update_time: 1_000,
+            lock_tiers: [LockTier::default(); MAX_LOCK_TIERS],
+            early_withdraw_penalty_bps: 0,
+            unstake_cooldown: 0,
+            bump: 255,
+            vault_bump: 255,
+            reward_vault_bump: 255,
+            rate_history: [RateChange::default(); RATE_HISTORY_LEN],
+            emission_schedule: EmissionSchedule::default(),
+        }
+    }
+
+    fn stake(amount: u64) -> StakeData {
+        StakeData {
+            pool: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            amount,
+            reward_debt: 0,
+            remained_reward: 0,
+            last_claim_time: 1_000,
+            lock_end: 0,
+            reward_multiplier_bps: BASIS_POINTS,
+            pending_unstake: 0,
+            unstake_requested_at: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn test_unpack_versioned_layouts() {
+        let stake = stake(100);
+        let without_bump = StakeData {
+            bump: 0,
+            ..stake.clone()
+        };
+
+        let mut v1 = vec![0; DISCRIMINATOR_SIZE + 1 + STAKE_DATA_V1_SIZE];
+        stake.pack(&mut v1).unwrap();
+        assert_eq!(v1[..DISCRIMINATOR_SIZE], StakeData::DISCRIMINATOR);
+        assert_eq!(v1[DISCRIMINATOR_SIZE], 1);
+        assert_eq!(StakeData::unpack(&v1), Ok(without_bump));
+
+        let mut current = vec![0; StakeData::LEN];
+        stake.pack(&mut current).unwrap();
+        assert_eq!(current[DISCRIMINATOR_SIZE], STAKE_DATA_VERSION);
+        assert_eq!(StakeData::unpack(&current), Ok(stake));
+    }
+
+    #[test]
+    fn test_unpack_legacy_layout() {
+        // `StakeData` of the original program, serialized without any header
+        let staker = Pubkey::new_unique();
+        let mut legacy = staker.to_bytes().to_vec();
+        legacy.extend_from_slice(&700u64.to_le_bytes());
+        legacy.extend_from_slice(&40u64.to_le_bytes());
+        legacy.extend_from_slice(&1_000i64.to_le_bytes());
+        assert_eq!(legacy.len(), LEGACY_STAKE_DATA_SIZE);
+
+        assert_eq!(
+            StakeData::unpack(&legacy),
+            Ok(StakeData {
+                pool: Pubkey::default(),
+                staker,
+                amount: 700,
+                reward_debt: 0,
+                remained_reward: 40,
+                last_claim_time: 1_000,
+                lock_end: 0,
+                reward_multiplier_bps: BASIS_POINTS,
+                pending_unstake: 0,
+                unstake_requested_at: 0,
+                bump: 0,
+            })
+        );
+        assert_eq!(
+            stake(100).pack(&mut legacy),
+            Err(MarketplaceError::InvalidStakeData.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_rejects_unknown_header() {
+        // a headerless body of the old size is not trusted as a stake account
+        let mut body = stake(100).try_to_vec().unwrap();
+        body.truncate(STAKE_DATA_V1_SIZE);
+        assert_eq!(
+            StakeData::unpack(&body),
+            Err(MarketplaceError::InvalidAccountDiscriminator)
+        );
+
+        let mut data = vec![0; StakeData::LEN];
+        stake(100).pack(&mut data).unwrap();
+
+        data[DISCRIMINATOR_SIZE] = STAKE_DATA_VERSION + 1;
+        assert_eq!(
+            StakeData::unpack(&data),
+            Err(MarketplaceError::InvalidStakeData)
+        );
+        data[DISCRIMINATOR_SIZE] = STAKE_DATA_VERSION;
+        data[0] = 0;
+        assert_eq!(
+            StakeData::unpack(&data),
+            Err(MarketplaceError::InvalidAccountDiscriminator)
+        );
+    }
+
+    #[test]
+    fn test_unpack_rejects_other_account_types() {
+        let mut vault = vec![0; VaultData::LEN];
+        VaultData {
+            pool: Pubkey::new_unique(),
+        }
+        .pack(&mut vault)
+        .unwrap();
+        assert_eq!(
+            ConfigData::unpack(&vault),
+            Err(MarketplaceError::InvalidAccountDiscriminator)
+        );
+
+        let mut config = vec![0; ConfigData::LEN];
+        ConfigData {
+            admin: Pubkey::new_unique(),
+            bump: 255,
+            pending_admin: Pubkey::default(),
+            guardian: Pubkey::default(),
+            paused: 0,
+        }
+        .pack(&mut config)
+        .unwrap();
+        assert!(ConfigData::unpack(&config).is_ok());
+        assert_eq!(
+            VaultData::unpack(&config),
+            Err(MarketplaceError::InvalidAccountDiscriminator)
+        );
+    }
+
+    #[test]
+    fn test_update_shares_emission_pro_rata() {
+        let mut pool = pool(10, 1_000, 400);
+        let alice = stake(100);
+        let bob = stake(300);
+
+        pool.update(1_010).unwrap();
+        assert_eq!(pool.reward_budget, 900);
+        assert_eq!(alice.pending_reward(pool.acc_reward_per_share), Ok(25));
+        assert_eq!(bob.pending_reward(pool.acc_reward_per_share), Ok(75));
+    }
+
+    #[test]
+    fn test_set_reward_rate_prices_past_period_at_old_rate() {
+        let mut pool = pool(10, 10_000, 100);
+        let alice = stake(100);
+
+        pool.set_reward_rate(30, 1_010).unwrap();
+        assert_eq!(alice.pending_reward(pool.acc_reward_per_share), Ok(100));
+        pool.update(1_020).unwrap();
+        assert_eq!(alice.pending_reward(pool.acc_reward_per_share), Ok(400));
+        assert_eq!(
+            pool.rate_history[0],
+            RateChange {
+                start_time: 1_010,
+                reward_rate: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_rate_history_drops_oldest() {
+        let mut pool = pool(10, 0, 0);
+        for rate in 1..=RATE_HISTORY_LEN as u64 + 2 {
+            pool.set_reward_rate(rate, 1_000 + rate as i64).unwrap();
+        }
+        let rates: Vec<u64> = pool
+            .rate_history
+            .iter()
+            .map(|change| change.reward_rate)
+            .collect();
+        assert_eq!(rates, vec![10, 9, 8, 7, 6, 5, 4, 3]);
+        // a clock that went backwards starts the rate at the last update
+        pool.set_reward_rate(11, 0).unwrap();
+        assert_eq!(pool.rate_history[0].start_time, 1_010);
+    }
+
+    #[test]
+    fn test_set_reward_rate_rejected_once_rate_step_started() {
+        let mut pool = pool(10, 10_000, 100);
+        pool.emission_schedule.rate_steps[0] = RateChange {
+            start_time: 1_020,
+            reward_rate: 5,
+        };
+
+        pool.set_reward_rate(30, 1_010).unwrap();
+        assert_eq!(
+            pool.set_reward_rate(40, 1_020),
+            Err(MarketplaceError::RateStepInForce)
+        );
+        assert_eq!(pool.reward_rate, 30);
+        assert_eq!(pool.rate_history[0].reward_rate, 30);
+    }
+
+    fn schedule(start_time: i64, end_time: i64, halving_period: i64) -> EmissionSchedule {
+        EmissionSchedule {
+            start_time,
+            end_time,
+            halving_period,
+            rate_steps: [RateChange::default(); MAX_RATE_STEPS],
+        }
+    }
+
+    #[test]
+    fn test_update_emits_only_within_schedule_window() {
+        let mut pool = pool(10, 10_000, 100);
+        pool.emission_schedule = schedule(1_050, 1_100, 0);
+
+        pool.update(1_040).unwrap();
+        assert_eq!(pool.reward_budget, 10_000);
+        pool.update(1_075).unwrap();
+        assert_eq!(pool.reward_budget, 9_750);
+        pool.update(2_000).unwrap();
+        assert_eq!(pool.reward_budget, 9_500);
+        assert_eq!(pool.last_update_time, 2_000);
+    }
+
+    #[test]
+    fn test_update_integrates_rate_steps() {
+        let mut pool = pool(10, 10_000, 100);
+        pool.emission_schedule.rate_steps[0] = RateChange {
+            start_time: 1_010,
+            reward_rate: 20,
+        };
+        pool.emission_schedule.rate_steps[1] = RateChange {
+            start_time: 1_020,
+            reward_rate: 5,
+        };
+
+        // 10 * 10 + 10 * 20 + 10 * 5
+        pool.update(1_030).unwrap();
+        assert_eq!(pool.reward_budget, 9_650);
+    }
+
+    #[test]
+    fn test_update_integrates_halvings() {
+        let mut pool = pool(80, 100_000, 100);
+        pool.emission_schedule = schedule(1_000, 0, 10);
+
+        // 10 * 80 + 10 * 40 + 10 * 20 + 5 * 10
+        pool.update(1_035).unwrap();
+        assert_eq!(pool.reward_budget, 100_000 - 1_450);
+
+        // the rate runs out after 64 halvings
+        pool.reward_rate = u64::MAX;
+        pool.last_update_time = 1_000 + 64 * 10;
+        pool.update(i64::MAX).unwrap();
+        assert_eq!(pool.reward_budget, 100_000 - 1_450);
+    }
+
+    #[test]
+    fn test_update_clock_regression() {
+        let mut pool = pool(10, 1_000, 100);
+        pool.update(1_010).unwrap();
+        let before = pool.clone();
+
+        pool.update(900).unwrap();
+        assert_eq!(pool, before);
+        pool.update(i64::MIN).unwrap();
+        assert_eq!(pool, before);
+    }
+
+    #[test]
+    fn test_update_long_idle_period_is_capped_by_budget() {
+        let mut pool = pool(u64::MAX, 5_000, 1);
+        pool.update(i64::MAX).unwrap();
+        assert_eq!(pool.reward_budget, 0);
+        assert_eq!(pool.acc_reward_per_share, 5_000 * ACC_REWARD_PRECISION);
+        assert_eq!(pool.last_update_time, i64::MAX);
+
+        // nothing left to emit
+        pool.last_update_time = 0;
+        pool.update(i64::MAX).unwrap();
+        assert_eq!(pool.acc_reward_per_share, 5_000 * ACC_REWARD_PRECISION);
+    }
+
+    #[test]
+    fn test_update_without_stakers_keeps_budget() {
+        let mut pool = pool(10, 1_000, 0);
+        pool.update(2_000).unwrap();
+        assert_eq!(pool.reward_budget, 1_000);
+        assert_eq!(pool.acc_reward_per_share, 0);
+        assert_eq!(pool.last_update_time, 2_000);
+    }
+
+    #[test]
+    fn test_checkpoint_huge_balance() {
+        // the whole supply staked and the whole supply emitted, over two updates
+        let half = u64::MAX / 2;
+        let mut pool = pool(half, u64::MAX, 2 * half);
+        let mut alice = stake(half);
+        let mut bob = stake(half);
+
+        pool.update(1_001).unwrap();
+        alice.checkpoint(pool.acc_reward_per_share).unwrap();
+        pool.update(1_002).unwrap();
+        alice.checkpoint(pool.acc_reward_per_share).unwrap();
+        bob.checkpoint(pool.acc_reward_per_share).unwrap();
+
+        assert_eq!(alice.remained_reward, half);
+        assert_eq!(bob.remained_reward, half);
+        assert_eq!(pool.reward_budget, u64::MAX - 2 * half);
+        assert_eq!(alice.pending_reward(pool.acc_reward_per_share), Ok(0));
+    }
+
+    #[test]
+    fn test_checkpoint_remained_reward_overflow() {
+        let mut pool = pool(10, 1_000, 1_000);
+        let mut staker = stake(1_000);
+        // an unclaimed reward that cannot take any more
+        staker.remained_reward = u64::MAX - 5;
+
+        pool.update(1_001).unwrap();
+        assert_eq!(staker.pending_reward(pool.acc_reward_per_share), Ok(10));
+        assert_eq!(
+            staker.checkpoint(pool.acc_reward_per_share),
+            Err(MarketplaceError::MathOverflow)
+        );
+    }
+
+    #[test]
+    fn test_whale_after_tiny_first_staker() {
+        let mut pool = pool(1_000_000_000, u64::MAX, 1);
+        let mut minnow = stake(1);
+
+        // the only stake takes the whole emission, pushing the accumulator to 1e25
+        pool.update(11_000).unwrap();
+        assert_eq!(pool.acc_reward_per_share, 10u128.pow(25));
+        minnow.checkpoint(pool.acc_reward_per_share).unwrap();
+        assert_eq!(minnow.remained_reward, 10_000_000_000_000);
+
+        let mut whale = stake(1_000_000_000_000_000);
+        whale.reset_reward_debt(pool.acc_reward_per_share).unwrap();
+        pool.replace_weight(0, whale.weight().unwrap()).unwrap();
+        assert_eq!(whale.pending_reward(pool.acc_reward_per_share), Ok(0));
+
+        pool.update(11_001).unwrap();
+        whale.checkpoint(pool.acc_reward_per_share).unwrap();
+        minnow.checkpoint(pool.acc_reward_per_share).unwrap();
+        assert_eq!(whale.remained_reward, 999_999_000);
+        assert_eq!(minnow.remained_reward, 10_000_000_000_000);
+    }
+
+    #[test]
+    fn test_accrued_reward_matches_u128_product() {
+        for (weight, acc) in [
+            (0, u128::MAX),
+            (1, ACC_REWARD_PRECISION - 1),
+            (12_345, 6_789 * ACC_REWARD_PRECISION + 42),
+            (u64::MAX, u64::MAX as u128),
+        ] {
+            assert_eq!(
+                accrued_reward(weight, acc),
+                Ok(weight as u128 * acc / ACC_REWARD_PRECISION)
+            );
+        }
+        // a u128 product would overflow here, the quotient does not
+        assert_eq!(
+            accrued_reward(u64::MAX, ACC_REWARD_PRECISION << 64),
+            Ok((u64::MAX as u128) << 64)
+        );
+    }
+
+    #[test]
+    fn test_accrued_reward_overflow() {
+        assert_eq!(
+            accrued_reward(u64::MAX, u128::MAX),
+            Err(MarketplaceError::MathOverflow)
+        );
+        assert_eq!(
+            stake(u64::MAX).pending_reward(u128::MAX),
+            Err(MarketplaceError::MathOverflow)
+        );
+    }
+
+    #[test]
+    fn test_lock_boosts_reward_share() {
+        let tier = LockTier {
+            duration: 90 * 24 * 60 * 60,
+            reward_multiplier_bps: 3 * BASIS_POINTS,
+        };
+        let mut pool = pool(10, 1_000, 0);
+        let mut locked = stake(100);
+        locked.apply_lock(&tier, 1_000).unwrap();
+        let unlocked = stake(100);
+        pool.replace_weight(0, locked.weight().unwrap()).unwrap();
+        pool.replace_weight(0, unlocked.weight().unwrap()).unwrap();
+        assert_eq!(pool.total_weight, 400);
+
+        pool.update(1_040).unwrap();
+        assert_eq!(locked.pending_reward(pool.acc_reward_per_share), Ok(300));
+        assert_eq!(unlocked.pending_reward(pool.acc_reward_per_share), Ok(100));
+
+        // the boost stops once the lock is over
+        locked.checkpoint(pool.acc_reward_per_share).unwrap();
+        locked.release_expired_lock(1_000 + tier.duration);
+        assert_eq!(locked.lock_end, 0);
+        assert_eq!(locked.weight(), Ok(100));
+    }
+
+    #[test]
+    fn test_apply_lock_keeps_longest_lock() {
+        let short = LockTier {
+            duration: 30,
+            reward_multiplier_bps: 12_000,
+        };
+        let long = LockTier {
+            duration: 180,
+            reward_multiplier_bps: 20_000,
+        };
+        let mut data = stake(100);
+        data.apply_lock(&long, 1_000).unwrap();
+        data.apply_lock(&short, 1_010).unwrap();
+        assert_eq!(data.lock_end, 1_180);
+        assert_eq!(data.reward_multiplier_bps, 20_000);
+
+        let mut pool = pool(0, 0, 0);
+        pool.lock_tiers[1] = short;
+        assert_eq!(pool.lock_tier(1), Ok(short));
+        assert_eq!(pool.lock_tier(0), Err(MarketplaceError::InvalidLockTier));
+        assert_eq!(pool.lock_tier(9), Err(MarketplaceError::InvalidLockTier));
+    }
+}
@@ -0,0 +1,319 @@
+use {
+    marketplace::{
+        error::MarketplaceError,
+        find_config_address, instruction, process_instruction,
+        state::{AccountData, ConfigData},
+        PAUSE_CLAIM, PAUSE_STAKE, PAUSE_WITHDRAW,
+    },
+    solana_program::pubkey::Pubkey,
+    solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext},
+    solana_sdk::{
+        instruction::{Instruction, InstructionError},
+        signature::{Keypair, Signer},
+        transaction::{Transaction, TransactionError},
+    },
+};
+
+struct Env {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+}
+
+impl Env {
+    // starts the program with the fee payer as admin
+    async fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let program_test =
+            ProgramTest::new("marketplace", program_id, processor!(process_instruction));
+        let context = program_test.start_with_context().await;
+        let mut env = Env {
+            context,
+            program_id,
+        };
+        let admin = env.context.payer.pubkey();
+        env.process(instruction::initialize(&program_id, &admin), &[])
+            .await
+            .unwrap();
+        env
+    }
+
+    async fn process(
+        &mut self,
+        instruction: Instruction,
+        signers: &[&Keypair],
+    ) -> Result<(), TransactionError> {
+        // a fresh blockhash keeps a retried transaction from being deduplicated
+        let recent_blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .map_err(|e| e.unwrap())
+    }
+
+    async fn config(&mut self) -> ConfigData {
+        let (config, _) = find_config_address(&self.program_id);
+        let account = self
+            .context
+            .banks_client
+            .get_account(config)
+            .await
+            .unwrap()
+            .unwrap();
+        ConfigData::unpack(&account.data).unwrap()
+    }
+}
+
+fn custom_error(error: MarketplaceError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+}
+
+#[tokio::test]
+async fn test_propose_and_accept_admin() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let new_admin = Keypair::new();
+
+    env.process(
+        instruction::propose_admin(&program_id, &admin, &new_admin.pubkey()),
+        &[],
+    )
+    .await
+    .unwrap();
+    let config = env.config().await;
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.pending_admin, new_admin.pubkey());
+
+    env.process(
+        instruction::accept_admin(&program_id, &new_admin.pubkey()),
+        &[&new_admin],
+    )
+    .await
+    .unwrap();
+    let config = env.config().await;
+    assert_eq!(config.admin, new_admin.pubkey());
+    assert_eq!(config.pending_admin, Pubkey::default());
+
+    // the previous admin lost its rights, the new one has them
+    let other = Pubkey::new_unique();
+    assert_eq!(
+        env.process(instruction::propose_admin(&program_id, &admin, &other), &[])
+            .await,
+        Err(custom_error(MarketplaceError::Unauthorized))
+    );
+    env.process(
+        instruction::propose_admin(&program_id, &new_admin.pubkey(), &other),
+        &[&new_admin],
+    )
+    .await
+    .unwrap();
+    assert_eq!(env.config().await.pending_admin, other);
+}
+
+#[tokio::test]
+async fn test_propose_admin_requires_admin() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let impostor = Keypair::new();
+
+    assert_eq!(
+        env.process(
+            instruction::propose_admin(&program_id, &impostor.pubkey(), &impostor.pubkey()),
+            &[&impostor],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::Unauthorized))
+    );
+    assert_eq!(env.config().await.pending_admin, Pubkey::default());
+}
+
+#[tokio::test]
+async fn test_accept_admin_requires_pending_admin() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let new_admin = Keypair::new();
+    let impostor = Keypair::new();
+
+    // nothing proposed yet
+    assert_eq!(
+        env.process(
+            instruction::accept_admin(&program_id, &new_admin.pubkey()),
+            &[&new_admin],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::NotPendingAdmin))
+    );
+
+    env.process(
+        instruction::propose_admin(&program_id, &admin, &new_admin.pubkey()),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        env.process(
+            instruction::accept_admin(&program_id, &impostor.pubkey()),
+            &[&impostor],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::NotPendingAdmin))
+    );
+
+    // the proposal can be replaced before it is accepted
+    env.process(
+        instruction::propose_admin(&program_id, &admin, &impostor.pubkey()),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        env.process(
+            instruction::accept_admin(&program_id, &new_admin.pubkey()),
+            &[&new_admin],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::NotPendingAdmin))
+    );
+    assert_eq!(env.config().await.admin, admin);
+}
+
+#[tokio::test]
+async fn test_renounce_admin() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let new_admin = Keypair::new();
+
+    env.process(
+        instruction::propose_admin(&program_id, &admin, &new_admin.pubkey()),
+        &[],
+    )
+    .await
+    .unwrap();
+    env.process(instruction::renounce_admin(&program_id, &admin), &[])
+        .await
+        .unwrap();
+    let config = env.config().await;
+    assert_eq!(config.admin, Pubkey::default());
+    assert_eq!(config.pending_admin, Pubkey::default());
+
+    // the pending proposal died with the admin
+    assert_eq!(
+        env.process(
+            instruction::accept_admin(&program_id, &new_admin.pubkey()),
+            &[&new_admin],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::NotPendingAdmin))
+    );
+    // and every admin instruction is frozen
+    assert_eq!(
+        env.process(
+            instruction::propose_admin(&program_id, &admin, &new_admin.pubkey()),
+            &[],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::AdminRenounced))
+    );
+    assert_eq!(
+        env.process(
+            instruction::create_pool(
+                &program_id,
+                &admin,
+                0,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                10,
+            ),
+            &[],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::AdminRenounced))
+    );
+}
+
+#[tokio::test]
+async fn test_guardian_can_pause_but_not_unpause() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let guardian = Keypair::new();
+
+    env.process(
+        instruction::set_guardian(&program_id, &admin, &guardian.pubkey()),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(env.config().await.guardian, guardian.pubkey());
+
+    env.process(
+        instruction::set_paused(&program_id, &guardian.pubkey(), PAUSE_STAKE),
+        &[&guardian],
+    )
+    .await
+    .unwrap();
+    env.process(
+        instruction::set_paused(&program_id, &guardian.pubkey(), PAUSE_STAKE | PAUSE_CLAIM),
+        &[&guardian],
+    )
+    .await
+    .unwrap();
+    assert_eq!(env.config().await.paused, PAUSE_STAKE | PAUSE_CLAIM);
+
+    // dropping a flag is an unpause, even while adding another
+    assert_eq!(
+        env.process(
+            instruction::set_paused(&program_id, &guardian.pubkey(), PAUSE_WITHDRAW),
+            &[&guardian],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::GuardianCannotUnpause))
+    );
+
+    env.process(instruction::set_paused(&program_id, &admin, 0), &[])
+        .await
+        .unwrap();
+    assert_eq!(env.config().await.paused, 0);
+}
+
+#[tokio::test]
+async fn test_set_paused_requires_admin_or_guardian() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let impostor = Keypair::new();
+
+    assert_eq!(
+        env.process(
+            instruction::set_paused(&program_id, &impostor.pubkey(), PAUSE_STAKE),
+            &[&impostor],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::Unauthorized))
+    );
+    assert_eq!(
+        env.process(
+            instruction::set_guardian(&program_id, &impostor.pubkey(), &impostor.pubkey()),
+            &[&impostor],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::Unauthorized))
+    );
+    assert_eq!(
+        env.process(instruction::set_paused(&program_id, &admin, 1 << 7), &[])
+            .await,
+        Err(custom_error(MarketplaceError::InvalidPauseFlags))
+    );
+    assert_eq!(env.config().await.paused, 0);
+}
@@ -0,0 +1,220 @@
+//! Compute units consumed by the staker instructions, read from the
+//! `consumed N of M compute units` line the runtime logs for the program.
+//!
+//! Only the SBF build is metered, so these tests run under `cargo test-sbf`.
+#![cfg(feature = "test-sbf")]
+
+use {
+    marketplace::{
+        find_pool_address, find_stake_address, instruction, process_instruction,
+        state::{AccountData, StakeData},
+    },
+    solana_program::{program_pack::Pack, pubkey::Pubkey, system_instruction},
+    solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext},
+    solana_sdk::{
+        account::{Account, AccountSharedData},
+        instruction::Instruction,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    },
+    spl_associated_token_account::get_associated_token_address,
+};
+
+const POOL_ID: u64 = 0;
+const STAKER_BALANCE: u64 = 10_000;
+
+struct Env {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    stake_mint: Pubkey,
+    staker: Keypair,
+}
+
+impl Env {
+    // initializes the program and creates a pool with a vault and a staker
+    // holding `STAKER_BALANCE` stake tokens
+    async fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let mut program_test =
+            ProgramTest::new("marketplace", program_id, processor!(process_instruction));
+        program_test.prefer_bpf(true);
+        let mut env = Env {
+            context: program_test.start_with_context().await,
+            program_id,
+            stake_mint: Pubkey::default(),
+            staker: Keypair::new(),
+        };
+
+        let admin = env.context.payer.pubkey();
+        let staker = env.staker.pubkey();
+        let mint = Keypair::new();
+        let rent = env.context.banks_client.get_rent().await.unwrap();
+        env.process(
+            &[
+                system_instruction::transfer(&admin, &staker, 1_000_000_000),
+                system_instruction::create_account(
+                    &admin,
+                    &mint.pubkey(),
+                    rent.minimum_balance(spl_token::state::Mint::LEN),
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(),
+                    &mint.pubkey(),
+                    &admin,
+                    None,
+                    0,
+                )
+                .unwrap(),
+                spl_associated_token_account::instruction::create_associated_token_account(
+                    &admin,
+                    &staker,
+                    &mint.pubkey(),
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    &mint.pubkey(),
+                    &get_associated_token_address(&staker, &mint.pubkey()),
+                    &admin,
+                    &[],
+                    STAKER_BALANCE,
+                )
+                .unwrap(),
+            ],
+            &[&mint],
+        )
+        .await;
+        env.stake_mint = mint.pubkey();
+
+        env.process(
+            &[
+                instruction::initialize(&program_id, &admin),
+                instruction::create_pool(
+                    &program_id,
+                    &admin,
+                    POOL_ID,
+                    &env.stake_mint,
+                    &env.stake_mint,
+                    0,
+                ),
+                instruction::generate_vault(&program_id, &admin, POOL_ID),
+            ],
+            &[],
+        )
+        .await;
+        env
+    }
+
+    fn transaction(&self, instructions: &[Instruction], signers: &[&Keypair]) -> Transaction {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            self.context.last_blockhash,
+        )
+    }
+
+    async fn process(&mut self, instructions: &[Instruction], signers: &[&Keypair]) {
+        let transaction = self.transaction(instructions, signers);
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+        self.context.last_blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+    }
+
+    // simulates `ix` signed by the staker and returns the units the program consumed
+    async fn compute_units(&mut self, ix: Instruction) -> u64 {
+        let staker = self.staker.insecure_clone();
+        let transaction = self.transaction(&[ix], &[&staker]);
+        let simulation = self
+            .context
+            .banks_client
+            .simulate_transaction(transaction)
+            .await
+            .unwrap();
+        simulation.result.unwrap().unwrap();
+        let prefix = format!("Program {} consumed ", self.program_id);
+        simulation
+            .simulation_details
+            .unwrap()
+            .logs
+            .iter()
+            .find_map(|log| log.strip_prefix(&prefix)?.split(' ').next()?.parse().ok())
+            .expect("the program is only metered as SBF, run under `cargo test-sbf`")
+    }
+
+    fn stake_ix(&self, amount: u64) -> Instruction {
+        instruction::stake(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &self.staker.pubkey(),
+            amount,
+            None,
+        )
+    }
+
+    fn withdraw_ix(&self, amount: u64) -> Instruction {
+        instruction::withdraw(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &self.staker.pubkey(),
+            amount,
+        )
+    }
+
+    // rewrites the position in the version 1 layout, which has no stored bump
+    async fn downgrade_stake_account(&mut self) {
+        let (pool, _) = find_pool_address(&self.program_id, POOL_ID);
+        let (stake_address, _) = find_stake_address(&self.program_id, &pool, &self.staker.pubkey());
+        let account = self
+            .context
+            .banks_client
+            .get_account(stake_address)
+            .await
+            .unwrap()
+            .unwrap();
+        let stake_data = StakeData::unpack(&account.data).unwrap();
+        let mut data = vec![0; StakeData::LEN - 1];
+        stake_data.pack(&mut data).unwrap();
+        self.context.set_account(
+            &stake_address,
+            &AccountSharedData::from(Account { data, ..account }),
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_stored_stake_bump_saves_compute_units() {
+    let mut env = Env::new().await;
+    let staker = env.staker.insecure_clone();
+    env.process(&[env.stake_ix(1_000)], &[&staker]).await;
+
+    let stake_units = env.compute_units(env.stake_ix(1_000)).await;
+    let withdraw_units = env.compute_units(env.withdraw_ix(1_000)).await;
+
+    // without a stored bump the stake PDA goes back to `find_program_address`
+    env.downgrade_stake_account().await;
+    let legacy_stake_units = env.compute_units(env.stake_ix(1_000)).await;
+    let legacy_withdraw_units = env.compute_units(env.withdraw_ix(1_000)).await;
+
+    assert!(
+        stake_units < legacy_stake_units,
+        "Stake: {} units with the stored bump, {} without",
+        stake_units,
+        legacy_stake_units
+    );
+    assert!(
+        withdraw_units < legacy_withdraw_units,
+        "Withdraw: {} units with the stored bump, {} without",
+        withdraw_units,
+        legacy_withdraw_units
+    );
+}
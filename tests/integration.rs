@@ -1,33 +1,1468 @@
-#![cfg(feature = "test-bpf")]
-
 use {
-    assert_matches::*,
-    solana_program::{
-        instruction::{AccountMeta, Instruction},
-        pubkey::Pubkey,
+    marketplace::{
+        error::MarketplaceError,
+        find_legacy_stake_address, find_legacy_vault_address, find_pool_address,
+        find_reward_vault_address, find_stake_address, find_vault_address, instruction,
+        process_instruction,
+        state::{AccountData, PoolData, StakeData},
+        LockTier, RateChange, BASIS_POINTS, PAUSE_ALL, PAUSE_CLAIM, PAUSE_STAKE, PAUSE_WITHDRAW,
+    },
+    solana_program::{clock::Clock, program_pack::Pack, pubkey::Pubkey, system_instruction},
+    solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext},
+    solana_sdk::{
+        account::{Account, AccountSharedData},
+        instruction::Instruction,
+        instruction::InstructionError,
+        signature::{Keypair, Signer},
+        transaction::{Transaction, TransactionError},
     },
-    solana_sdk::{signature::Signer, transaction::Transaction},
-    solana_validator::test_validator::*,
+    spl_associated_token_account::get_associated_token_address,
+    std::convert::TryInto,
 };
 
-#[test]
-fn test_validator_transaction() {
-    let program_id = Pubkey::new_unique();
+const POOL_ID: u64 = 0;
+const REWARD_RATE: u64 = 10;
+const REWARD_FUNDING: u64 = 1_000_000;
+const STAKER_BALANCE: u64 = 10_000;
+const LOCK_DURATION: i64 = 100;
+
+struct Env {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    stake_mint: Pubkey,
+    reward_mint: Pubkey,
+    staker: Keypair,
+}
 
-    let (test_validator, payer) = TestValidatorGenesis::default()
-        .add_program("bpf_program_template", program_id)
-        .start();
-    let (rpc_client, recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+impl Env {
+    // initializes the program, creates a funded pool with a vault and a staker
+    // holding `STAKER_BALANCE` stake tokens
+    async fn new() -> Self {
+        Self::setup(false).await
+    }
 
-    let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
+    // same as `new` with a pool paying rewards in the stake mint
+    async fn new_single_mint() -> Self {
+        Self::setup(true).await
+    }
+
+    async fn setup(single_mint: bool) -> Self {
+        let program_id = Pubkey::new_unique();
+        let program_test =
+            ProgramTest::new("marketplace", program_id, processor!(process_instruction));
+        let context = program_test.start_with_context().await;
+
+        let mut env = Env {
+            context,
             program_id,
-            accounts: vec![AccountMeta::new(payer.pubkey(), false)],
-            data: vec![1, 2, 3],
-        }],
-        Some(&payer.pubkey()),
+            stake_mint: Pubkey::default(),
+            reward_mint: Pubkey::default(),
+            staker: Keypair::new(),
+        };
+        env.stake_mint = env.create_mint().await;
+        env.reward_mint = if single_mint {
+            env.stake_mint
+        } else {
+            env.create_mint().await
+        };
+
+        let admin = env.context.payer.pubkey();
+        let staker = env.staker.pubkey();
+        env.process(
+            &[system_instruction::transfer(&admin, &staker, 1_000_000_000)],
+            &[],
+        )
+        .await;
+        let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+        env.mint_to(&stake_mint, &staker, STAKER_BALANCE).await;
+        env.mint_to(&reward_mint, &admin, REWARD_FUNDING).await;
+
+        env.process(
+            &[
+                instruction::initialize(&program_id, &admin),
+                instruction::create_pool(
+                    &program_id,
+                    &admin,
+                    POOL_ID,
+                    &stake_mint,
+                    &reward_mint,
+                    REWARD_RATE,
+                ),
+                instruction::generate_vault(&program_id, &admin, POOL_ID),
+                instruction::fund_rewards(
+                    &program_id,
+                    POOL_ID,
+                    &reward_mint,
+                    &admin,
+                    REWARD_FUNDING,
+                ),
+            ],
+            &[],
+        )
+        .await;
+        env
+    }
+
+    // sends `instructions` paid by the fee payer and signed by `signers`
+    async fn process(&mut self, instructions: &[Instruction], signers: &[&Keypair]) {
+        self.try_process(instructions, signers).await.unwrap();
+    }
+
+    async fn try_process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), TransactionError> {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let recent_blockhash = self
+            .context
+            .banks_client
+            .get_latest_blockhash()
+            .await
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .map_err(|e| e.unwrap())
+    }
+
+    async fn create_mint(&mut self) -> Pubkey {
+        let mint = Keypair::new();
+        let payer = self.context.payer.pubkey();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        self.process(
+            &[
+                system_instruction::create_account(
+                    &payer,
+                    &mint.pubkey(),
+                    rent.minimum_balance(spl_token::state::Mint::LEN),
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(),
+                    &mint.pubkey(),
+                    &payer,
+                    None,
+                    0,
+                )
+                .unwrap(),
+            ],
+            &[&mint],
+        )
+        .await;
+        mint.pubkey()
+    }
+
+    // creates the associated token account of `owner` and mints `amount` to it
+    async fn mint_to(&mut self, mint: &Pubkey, owner: &Pubkey, amount: u64) {
+        let payer = self.context.payer.pubkey();
+        self.process(
+            &[
+                spl_associated_token_account::instruction::create_associated_token_account(
+                    &payer,
+                    owner,
+                    mint,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    mint,
+                    &get_associated_token_address(owner, mint),
+                    &payer,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+            ],
+            &[],
+        )
+        .await;
+    }
+
+    async fn token_balance(&mut self, owner: &Pubkey, mint: &Pubkey) -> u64 {
+        let account = self
+            .context
+            .banks_client
+            .get_account(get_associated_token_address(owner, mint))
+            .await
+            .unwrap()
+            .unwrap();
+        spl_token::state::Account::unpack(&account.data)
+            .unwrap()
+            .amount
+    }
+
+    fn stake_address(&self) -> Pubkey {
+        let (pool, _) = find_pool_address(&self.program_id, POOL_ID);
+        find_stake_address(&self.program_id, &pool, &self.staker.pubkey()).0
+    }
+
+    async fn stake_data(&mut self) -> StakeData {
+        let account = self
+            .context
+            .banks_client
+            .get_account(self.stake_address())
+            .await
+            .unwrap()
+            .unwrap();
+        StakeData::unpack(&account.data).unwrap()
+    }
+
+    async fn pool_data(&mut self) -> PoolData {
+        let (pool, _) = find_pool_address(&self.program_id, POOL_ID);
+        let account = self
+            .context
+            .banks_client
+            .get_account(pool)
+            .await
+            .unwrap()
+            .unwrap();
+        PoolData::unpack(&account.data).unwrap()
+    }
+
+    async fn now(&mut self) -> i64 {
+        let clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp
+    }
+
+    // moves the clock forward by `seconds`
+    async fn warp(&mut self, seconds: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += seconds;
+        self.context.set_sysvar(&clock);
+    }
+
+    async fn stake(&mut self, amount: u64) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::stake(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &staker.pubkey(),
+            amount,
+            None,
+        );
+        self.process(&[ix], &[&staker]).await;
+    }
+
+    async fn withdraw(&mut self, amount: u64) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::withdraw(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &staker.pubkey(),
+            amount,
+        );
+        self.process(&[ix], &[&staker]).await;
+    }
+
+    // simulates `GetPendingReward` and decodes its return data
+    async fn pending_reward(&mut self) -> u64 {
+        let ix = instruction::get_pending_reward(&self.program_id, POOL_ID, &self.staker.pubkey());
+        let recent_blockhash = self
+            .context
+            .banks_client
+            .get_latest_blockhash()
+            .await
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.context.payer.pubkey()),
+            &[&self.context.payer],
+            recent_blockhash,
+        );
+        let simulation = self
+            .context
+            .banks_client
+            .simulate_transaction(transaction)
+            .await
+            .unwrap();
+        simulation.result.unwrap().unwrap();
+        let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+        assert_eq!(return_data.program_id, self.program_id);
+        u64::from_le_bytes(return_data.data.try_into().unwrap())
+    }
+
+    async fn compound(&mut self) -> Result<(), TransactionError> {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::compound(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &staker.pubkey(),
+        );
+        self.try_process(&[ix], &[&staker]).await
+    }
+
+    // configures a single lock tier of `LOCK_DURATION` seconds
+    async fn set_lock_tier(&mut self, reward_multiplier_bps: u64, early_withdraw_penalty_bps: u64) {
+        let ix = instruction::set_lock_tiers(
+            &self.program_id,
+            &self.context.payer.pubkey(),
+            POOL_ID,
+            vec![LockTier {
+                duration: LOCK_DURATION,
+                reward_multiplier_bps,
+            }],
+            early_withdraw_penalty_bps,
+        );
+        self.process(&[ix], &[]).await;
+    }
+
+    async fn stake_locked(&mut self, amount: u64) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::stake(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &staker.pubkey(),
+            amount,
+            Some(0),
+        );
+        self.process(&[ix], &[&staker]).await;
+    }
+
+    async fn mint_supply(&mut self, mint: &Pubkey) -> u64 {
+        let account = self
+            .context
+            .banks_client
+            .get_account(*mint)
+            .await
+            .unwrap()
+            .unwrap();
+        spl_token::state::Mint::unpack(&account.data)
+            .unwrap()
+            .supply
+    }
+
+    async fn set_unstake_cooldown(&mut self, unstake_cooldown: i64) {
+        let ix = instruction::set_unstake_cooldown(
+            &self.program_id,
+            &self.context.payer.pubkey(),
+            POOL_ID,
+            unstake_cooldown,
+        );
+        self.process(&[ix], &[]).await;
+    }
+
+    async fn request_unstake(&mut self, amount: u64) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::request_unstake(&self.program_id, POOL_ID, &staker.pubkey(), amount);
+        self.process(&[ix], &[&staker]).await;
+    }
+
+    async fn complete_unstake(&mut self) -> Result<(), TransactionError> {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::complete_unstake(
+            &self.program_id,
+            POOL_ID,
+            &self.stake_mint,
+            &staker.pubkey(),
+        );
+        // a fresh blockhash keeps a retried transaction from being deduplicated
+        self.context.get_new_latest_blockhash().await.unwrap();
+        self.try_process(&[ix], &[&staker]).await
+    }
+
+    async fn cancel_unstake(&mut self) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::cancel_unstake(&self.program_id, POOL_ID, &staker.pubkey());
+        self.process(&[ix], &[&staker]).await;
+    }
+
+    async fn close_stake_account(&mut self) -> Result<(), TransactionError> {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::close_stake_account(&self.program_id, POOL_ID, &staker.pubkey());
+        self.context.get_new_latest_blockhash().await.unwrap();
+        self.try_process(&[ix], &[&staker]).await
+    }
+
+    async fn claim(&mut self) {
+        let staker = self.staker.insecure_clone();
+        let ix = instruction::claim(
+            &self.program_id,
+            POOL_ID,
+            &self.reward_mint,
+            &staker.pubkey(),
+        );
+        self.process(&[ix], &[&staker]).await;
+    }
+}
+
+fn custom_error(error: MarketplaceError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+}
+
+#[tokio::test]
+async fn test_generate_vault_and_fund_rewards() {
+    let mut env = Env::new().await;
+    let (pool, _) = find_pool_address(&env.program_id, POOL_ID);
+    let (vault, _) = find_vault_address(&env.program_id, &pool);
+    let (reward_vault, _) = find_reward_vault_address(&env.program_id, &pool);
+
+    let vault_account = env
+        .context
+        .banks_client
+        .get_account(vault)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(vault_account.owner, env.program_id);
+
+    let reward_mint = env.reward_mint;
+    assert_eq!(
+        env.token_balance(&reward_vault, &reward_mint).await,
+        REWARD_FUNDING
+    );
+    let pool_data = env.pool_data().await;
+    assert_eq!(pool_data.reward_budget, REWARD_FUNDING);
+    assert_eq!(pool_data.reward_rate, REWARD_RATE);
+}
+
+#[tokio::test]
+async fn test_stake() {
+    let mut env = Env::new().await;
+    let staker = env.staker.pubkey();
+    let (stake_mint, now) = (env.stake_mint, env.now().await);
+
+    env.stake(1_000).await;
+
+    let (pool, _) = find_pool_address(&env.program_id, POOL_ID);
+    let (vault, _) = find_vault_address(&env.program_id, &pool);
+    assert_eq!(env.token_balance(&staker, &stake_mint).await, 9_000);
+    assert_eq!(env.token_balance(&vault, &stake_mint).await, 1_000);
+
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.pool, pool);
+    assert_eq!(stake_data.staker, staker);
+    assert_eq!(stake_data.amount, 1_000);
+    assert_eq!(stake_data.remained_reward, 0);
+    assert_eq!(stake_data.last_claim_time, now);
+    assert_eq!(
+        stake_data.bump,
+        find_stake_address(&env.program_id, &pool, &staker).1
+    );
+    assert_eq!(env.pool_data().await.total_staked, 1_000);
+}
+
+#[tokio::test]
+async fn test_claim_after_warp() {
+    let mut env = Env::new().await;
+    let staker = env.staker.pubkey();
+    let reward_mint = env.reward_mint;
+
+    env.stake(1_000).await;
+    env.warp(100).await;
+    env.claim().await;
+
+    // the only staker earns the whole emission
+    assert_eq!(
+        env.token_balance(&staker, &reward_mint).await,
+        100 * REWARD_RATE
+    );
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.remained_reward, 0);
+    assert_eq!(stake_data.last_claim_time, env.now().await);
+    assert_eq!(
+        env.pool_data().await.reward_budget,
+        REWARD_FUNDING - 100 * REWARD_RATE
+    );
+}
+
+#[tokio::test]
+async fn test_withdraw_keeps_accrued_reward() {
+    let mut env = Env::new().await;
+    let staker = env.staker.pubkey();
+    let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+
+    env.stake(1_000).await;
+    env.warp(50).await;
+    env.withdraw(500).await;
+
+    assert_eq!(env.token_balance(&staker, &stake_mint).await, 9_500);
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 500);
+    assert_eq!(stake_data.remained_reward, 50 * REWARD_RATE);
+    assert_eq!(env.pool_data().await.total_staked, 500);
+
+    // the reward earned before the withdraw is paid on the next claim
+    env.warp(50).await;
+    env.claim().await;
+    assert_eq!(
+        env.token_balance(&staker, &reward_mint).await,
+        100 * REWARD_RATE
+    );
+
+    env.withdraw(500).await;
+    assert_eq!(
+        env.token_balance(&staker, &stake_mint).await,
+        STAKER_BALANCE
+    );
+    assert_eq!(env.stake_data().await.amount, 0);
+}
+
+#[tokio::test]
+async fn test_get_pending_reward() {
+    let mut env = Env::new().await;
+    env.stake(1_000).await;
+    assert_eq!(env.pending_reward().await, 0);
+
+    env.warp(100).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(env.pending_reward().await, 100 * REWARD_RATE);
+    // the view writes nothing back
+    assert_eq!(env.stake_data().await, stake_data);
+
+    env.claim().await;
+    assert_eq!(env.pending_reward().await, 0);
+}
+
+#[tokio::test]
+async fn test_compound() {
+    let mut env = Env::new_single_mint().await;
+    let staker = env.staker.pubkey();
+    let mint = env.stake_mint;
+    let (pool, _) = find_pool_address(&env.program_id, POOL_ID);
+    let (vault, _) = find_vault_address(&env.program_id, &pool);
+    let (reward_vault, _) = find_reward_vault_address(&env.program_id, &pool);
+
+    env.stake(1_000).await;
+    env.warp(100).await;
+    env.compound().await.unwrap();
+
+    let reward = 100 * REWARD_RATE;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 1_000 + reward);
+    assert_eq!(stake_data.remained_reward, 0);
+    assert_eq!(env.pool_data().await.total_staked, 1_000 + reward);
+    assert_eq!(env.token_balance(&vault, &mint).await, 1_000 + reward);
+    assert_eq!(
+        env.token_balance(&reward_vault, &mint).await,
+        REWARD_FUNDING - reward
+    );
+    // nothing went through the staker's account
+    assert_eq!(env.token_balance(&staker, &mint).await, 9_000);
+
+    // the compounded reward earns like the rest of the principal
+    env.warp(100).await;
+    assert_eq!(env.pending_reward().await, 100 * REWARD_RATE);
+}
+
+#[tokio::test]
+async fn test_compound_rejects_different_mints() {
+    let mut env = Env::new().await;
+    env.stake(1_000).await;
+    env.warp(100).await;
+
+    assert_eq!(
+        env.compound().await.unwrap_err(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(MarketplaceError::CompoundMintMismatch as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_pause_blocks_flagged_instructions() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+    let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+    let paused = TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(MarketplaceError::Paused as u32),
+    );
+
+    env.stake(1_000).await;
+    env.warp(100).await;
+    env.process(
+        &[instruction::set_paused(
+            &program_id,
+            &admin,
+            PAUSE_STAKE | PAUSE_CLAIM,
+        )],
+        &[],
+    )
+    .await;
+
+    let stake = instruction::stake(
+        &program_id,
+        POOL_ID,
+        &stake_mint,
+        &staker.pubkey(),
+        10,
+        None,
+    );
+    assert_eq!(
+        env.try_process(&[stake], &[&staker]).await,
+        Err(paused.clone())
+    );
+    let claim = instruction::claim(&program_id, POOL_ID, &reward_mint, &staker.pubkey());
+    assert_eq!(env.try_process(&[claim], &[&staker]).await, Err(paused));
+
+    // withdrawals stay open, and rewards keep accruing for the next claim
+    env.withdraw(1_000).await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE
+    );
+
+    env.process(&[instruction::set_paused(&program_id, &admin, 0)], &[])
+        .await;
+    env.claim().await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &reward_mint).await,
+        100 * REWARD_RATE
+    );
+}
+
+#[tokio::test]
+async fn test_pause_blocks_unstake_queue() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+    let request_unstake = instruction::request_unstake(&program_id, POOL_ID, &staker.pubkey(), 400);
+    let cancel_unstake = instruction::cancel_unstake(&program_id, POOL_ID, &staker.pubkey());
+
+    env.set_unstake_cooldown(100).await;
+    env.stake(1_000).await;
+
+    // entering the queue is a withdrawal
+    env.process(
+        &[instruction::set_paused(&program_id, &admin, PAUSE_WITHDRAW)],
+        &[],
+    )
+    .await;
+    assert_eq!(
+        env.try_process(&[request_unstake], &[&staker]).await,
+        Err(custom_error(MarketplaceError::Paused))
+    );
+    env.process(&[instruction::set_paused(&program_id, &admin, 0)], &[])
+        .await;
+    env.request_unstake(400).await;
+
+    // leaving it is a stake
+    env.process(
+        &[instruction::set_paused(&program_id, &admin, PAUSE_STAKE)],
+        &[],
+    )
+    .await;
+    assert_eq!(
+        env.try_process(&[cancel_unstake], &[&staker]).await,
+        Err(custom_error(MarketplaceError::Paused))
+    );
+    // a fresh blockhash keeps the second unpause from being deduplicated
+    env.context.get_new_latest_blockhash().await.unwrap();
+    env.process(&[instruction::set_paused(&program_id, &admin, 0)], &[])
+        .await;
+    env.cancel_unstake().await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 1_000);
+    assert_eq!(stake_data.pending_unstake, 0);
+}
+
+#[tokio::test]
+async fn test_emergency_withdraw_forfeits_rewards() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+
+    env.stake(1_000).await;
+    env.warp(100).await;
+    env.process(
+        &[instruction::request_unstake(
+            &program_id,
+            POOL_ID,
+            &staker.pubkey(),
+            400,
+        )],
+        &[&staker],
+    )
+    .await;
+    assert!(env.stake_data().await.remained_reward > 0);
+    env.process(
+        &[instruction::set_paused(&program_id, &admin, PAUSE_ALL)],
+        &[],
+    )
+    .await;
+
+    // the principal in cooldown comes out along with the staked amount
+    env.process(
+        &[instruction::emergency_withdraw(
+            &program_id,
+            POOL_ID,
+            &stake_mint,
+            &staker.pubkey(),
+        )],
+        &[&staker],
+    )
+    .await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE
+    );
+
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 0);
+    assert_eq!(stake_data.pending_unstake, 0);
+    assert_eq!(stake_data.remained_reward, 0);
+    let pool = env.pool_data().await;
+    assert_eq!(pool.total_staked, 0);
+    assert_eq!(pool.total_weight, 0);
+    // the sole staker earned everything emitted so far, all of it forfeited
+    assert_eq!(pool.reward_budget, REWARD_FUNDING);
+}
+
+#[tokio::test]
+async fn test_emergency_withdraw_ignores_locks_and_cooldowns() {
+    let mut env = Env::new().await;
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+    let supply = env.mint_supply(&stake_mint).await;
+    let emergency_withdraw =
+        instruction::emergency_withdraw(&env.program_id, POOL_ID, &stake_mint, &staker.pubkey());
+    env.set_unstake_cooldown(100).await;
+    env.set_lock_tier(15_000, 1_000).await;
+
+    // locked principal comes back whole, no penalty is burned
+    env.stake_locked(1_000).await;
+    env.process(std::slice::from_ref(&emergency_withdraw), &[&staker])
+        .await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE
+    );
+    assert_eq!(env.mint_supply(&stake_mint).await, supply);
+
+    // principal cooling down comes back without waiting for the queue
+    env.stake(1_000).await;
+    env.request_unstake(400).await;
+    env.context.get_new_latest_blockhash().await.unwrap();
+    env.process(&[emergency_withdraw], &[&staker]).await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE
+    );
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 0);
+    assert_eq!(stake_data.pending_unstake, 0);
+    let pool = env.pool_data().await;
+    assert_eq!(pool.total_staked, 0);
+    assert_eq!(pool.total_weight, 0);
+}
+
+#[tokio::test]
+async fn test_set_reward_rate_keeps_accrued_reward() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+
+    env.stake(1_000).await;
+    env.warp(100).await;
+    env.process(
+        &[instruction::set_reward_rate(
+            &program_id,
+            &admin,
+            POOL_ID,
+            3 * REWARD_RATE,
+        )],
+        &[],
+    )
+    .await;
+    let changed_at = env.now().await;
+    env.warp(100).await;
+    assert_eq!(
+        env.pending_reward().await,
+        100 * REWARD_RATE + 300 * REWARD_RATE
     );
-    transaction.sign(&[&payer], recent_blockhash);
 
-    assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
+    let pool = env.pool_data().await;
+    assert_eq!(pool.reward_rate, 3 * REWARD_RATE);
+    assert_eq!(
+        pool.rate_history[0],
+        RateChange {
+            start_time: changed_at,
+            reward_rate: 3 * REWARD_RATE,
+        }
+    );
+    // the rate the pool was created with
+    assert_eq!(pool.rate_history[1].reward_rate, REWARD_RATE);
+    assert!(pool.rate_history[1].start_time <= changed_at - 100);
+
+    assert_eq!(
+        env.try_process(
+            &[instruction::set_reward_rate(
+                &program_id,
+                &staker.pubkey(),
+                POOL_ID,
+                0,
+            )],
+            &[&staker],
+        )
+        .await,
+        Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(MarketplaceError::Unauthorized as u32)
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_emission_schedule_bounds_accrual() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let (staker, reward_mint) = (env.staker.pubkey(), env.reward_mint);
+    let now = env.now().await;
+
+    env.process(
+        &[instruction::set_emission_schedule(
+            &program_id,
+            &admin,
+            POOL_ID,
+            now + 50,
+            now + 150,
+            0,
+            vec![RateChange {
+                start_time: now + 100,
+                reward_rate: 2 * REWARD_RATE,
+            }],
+        )],
+        &[],
+    )
+    .await;
+    env.stake(1_000).await;
+    env.warp(200).await;
+
+    // nothing before the start, double rate after the step, nothing after the end
+    assert_eq!(
+        env.pending_reward().await,
+        50 * REWARD_RATE + 50 * 2 * REWARD_RATE
+    );
+    env.claim().await;
+    assert_eq!(
+        env.token_balance(&staker, &reward_mint).await,
+        150 * REWARD_RATE
+    );
+
+    assert_eq!(
+        env.try_process(
+            &[instruction::set_emission_schedule(
+                &program_id,
+                &admin,
+                POOL_ID,
+                now + 100,
+                now + 100,
+                0,
+                vec![],
+            )],
+            &[],
+        )
+        .await,
+        Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(MarketplaceError::InvalidEmissionSchedule as u32)
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_set_reward_rate_rejected_once_rate_step_started() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let now = env.now().await;
+
+    env.process(
+        &[instruction::set_emission_schedule(
+            &program_id,
+            &admin,
+            POOL_ID,
+            0,
+            0,
+            0,
+            vec![RateChange {
+                start_time: now + 100,
+                reward_rate: 2 * REWARD_RATE,
+            }],
+        )],
+        &[],
+    )
+    .await;
+    // until the step starts the rate still applies
+    env.process(
+        &[instruction::set_reward_rate(
+            &program_id,
+            &admin,
+            POOL_ID,
+            3 * REWARD_RATE,
+        )],
+        &[],
+    )
+    .await;
+
+    env.warp(100).await;
+    assert_eq!(
+        env.try_process(
+            &[instruction::set_reward_rate(
+                &program_id,
+                &admin,
+                POOL_ID,
+                4 * REWARD_RATE,
+            )],
+            &[],
+        )
+        .await,
+        Err(custom_error(MarketplaceError::RateStepInForce))
+    );
+    let pool = env.pool_data().await;
+    assert_eq!(pool.reward_rate, 3 * REWARD_RATE);
+    assert_eq!(pool.rate_history[0].reward_rate, 3 * REWARD_RATE);
+}
+
+#[tokio::test]
+async fn test_complete_unstake_after_cooldown() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+
+    env.set_unstake_cooldown(100).await;
+    env.stake(1_000).await;
+    let withdraw = instruction::withdraw(&program_id, POOL_ID, &stake_mint, &staker.pubkey(), 400);
+    assert_eq!(
+        env.try_process(&[withdraw], &[&staker]).await,
+        Err(custom_error(MarketplaceError::UnstakeCooldownRequired))
+    );
+
+    env.request_unstake(400).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 600);
+    assert_eq!(stake_data.pending_unstake, 400);
+    let pool = env.pool_data().await;
+    assert_eq!(pool.total_staked, 600);
+    assert_eq!(pool.total_weight, 600);
+
+    env.warp(99).await;
+    assert_eq!(
+        env.complete_unstake().await,
+        Err(custom_error(MarketplaceError::CooldownNotElapsed))
+    );
+    env.warp(1).await;
+    env.complete_unstake().await.unwrap();
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE - 600
+    );
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 600);
+    assert_eq!(stake_data.pending_unstake, 0);
+    assert_eq!(
+        env.complete_unstake().await,
+        Err(custom_error(MarketplaceError::NoPendingUnstake))
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_unstake_restores_weight() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let stake_mint = env.stake_mint;
+
+    // a second staker of the same size makes the share of the pool visible
+    env.mint_to(&stake_mint, &admin, 1_000).await;
+    env.process(
+        &[instruction::stake(
+            &program_id,
+            POOL_ID,
+            &stake_mint,
+            &admin,
+            1_000,
+            None,
+        )],
+        &[],
+    )
+    .await;
+    env.set_unstake_cooldown(100).await;
+    env.stake(1_000).await;
+
+    env.warp(30).await;
+    env.request_unstake(500).await;
+    // half the stake cooling down, a third of the emission
+    env.warp(30).await;
+    env.cancel_unstake().await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 1_000);
+    assert_eq!(stake_data.pending_unstake, 0);
+    assert_eq!(stake_data.unstake_requested_at, 0);
+    let pool = env.pool_data().await;
+    assert_eq!(pool.total_staked, 2_000);
+    assert_eq!(pool.total_weight, 2_000);
+
+    env.warp(30).await;
+    assert_eq!(
+        env.pending_reward().await,
+        15 * REWARD_RATE + 10 * REWARD_RATE + 15 * REWARD_RATE
+    );
+}
+
+#[tokio::test]
+async fn test_set_unstake_cooldown_rejects_negative() {
+    let mut env = Env::new().await;
+    let ix = instruction::set_unstake_cooldown(
+        &env.program_id,
+        &env.context.payer.pubkey(),
+        POOL_ID,
+        -1,
+    );
+    assert_eq!(
+        env.try_process(&[ix], &[]).await,
+        Err(custom_error(MarketplaceError::InvalidUnstakeCooldown))
+    );
+    env.set_unstake_cooldown(0).await;
+    assert_eq!(env.pool_data().await.unstake_cooldown, 0);
+}
+
+#[tokio::test]
+async fn test_close_stake_account_rejects_open_position() {
+    let mut env = Env::new().await;
+    let not_empty = Err(custom_error(MarketplaceError::StakeAccountNotEmpty));
+
+    env.stake(1_000).await;
+    assert_eq!(env.close_stake_account().await, not_empty);
+
+    env.warp(10).await;
+    env.request_unstake(1_000).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!((stake_data.amount, stake_data.pending_unstake), (0, 1_000));
+    assert_eq!(env.close_stake_account().await, not_empty);
+
+    env.complete_unstake().await.unwrap();
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.pending_unstake, 0);
+    assert_eq!(stake_data.remained_reward, 10 * REWARD_RATE);
+    assert_eq!(env.close_stake_account().await, not_empty);
+
+    env.claim().await;
+    env.close_stake_account().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_close_stake_account_refunds_and_restakes() {
+    let mut env = Env::new().await;
+    let (staker, stake_mint) = (env.staker.pubkey(), env.stake_mint);
+    let stake_address = env.stake_address();
+
+    env.stake(1_000).await;
+    env.withdraw(1_000).await;
+    let rent = env
+        .context
+        .banks_client
+        .get_account(stake_address)
+        .await
+        .unwrap()
+        .unwrap()
+        .lamports;
+    let balance = env.context.banks_client.get_balance(staker).await.unwrap();
+
+    env.close_stake_account().await.unwrap();
+    assert_eq!(
+        env.context.banks_client.get_balance(staker).await.unwrap(),
+        balance + rent
+    );
+    assert_eq!(
+        env.context
+            .banks_client
+            .get_account(stake_address)
+            .await
+            .unwrap(),
+        None
+    );
+
+    // the next stake recreates the account from scratch
+    env.stake(500).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.staker, staker);
+    assert_eq!(stake_data.amount, 500);
+    assert_eq!(stake_data.remained_reward, 0);
+    assert_eq!(env.pool_data().await.total_staked, 500);
+    env.withdraw(500).await;
+    assert_eq!(
+        env.token_balance(&staker, &stake_mint).await,
+        STAKER_BALANCE
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_stake_account() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+    let stake_address = env.stake_address();
+    let rent = env.context.banks_client.get_rent().await.unwrap();
+
+    // rewrite the position in the version 1 layout, which ends before the bump
+    env.stake(1_000).await;
+    let stake_data = env.stake_data().await;
+    let v1_len = StakeData::LEN - 1;
+    let mut data = vec![0; v1_len];
+    stake_data.pack(&mut data).unwrap();
+    assert_eq!(data[8], 1);
+    env.context.set_account(
+        &stake_address,
+        &AccountSharedData::from(Account {
+            lamports: rent.minimum_balance(v1_len),
+            data,
+            owner: program_id,
+            executable: false,
+            rent_epoch: 0,
+        }),
+    );
+
+    // version 1 keeps working until migrated
+    env.stake(500).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 1_500);
+    assert_eq!(stake_data.bump, 0);
+
+    // a second migration in the same transaction finds nothing to upgrade
+    let migrate = instruction::migrate_stake_account(&program_id, POOL_ID, &staker.pubkey());
+    assert_eq!(
+        env.try_process(&[migrate.clone(), migrate.clone()], &[&staker])
+            .await,
+        Err(TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(MarketplaceError::StakeAccountAlreadyMigrated as u32)
+        ))
+    );
+    env.process(&[migrate], &[&staker]).await;
+    let account = env
+        .context
+        .banks_client
+        .get_account(stake_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), StakeData::LEN);
+    assert_eq!(account.data[8], 2);
+    assert_eq!(account.lamports, rent.minimum_balance(StakeData::LEN));
+    let (pool, _) = find_pool_address(&program_id, POOL_ID);
+    let stake_data = env.stake_data().await;
+    assert_eq!(
+        stake_data.bump,
+        find_stake_address(&program_id, &pool, &staker.pubkey()).1
+    );
+    assert_eq!(stake_data.amount, 1_500);
+
+    env.withdraw(1_500).await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_stake_account() {
+    let mut env = Env::new_single_mint().await;
+    let program_id = env.program_id;
+    let staker = env.staker.insecure_clone();
+    let mint = env.stake_mint;
+    let rent = env.context.banks_client.get_rent().await.unwrap();
+    let (pool, _) = find_pool_address(&program_id, POOL_ID);
+    let (vault, _) = find_vault_address(&program_id, &pool);
+    let (reward_vault, _) = find_reward_vault_address(&program_id, &pool);
+    let (legacy_vault, _) = find_legacy_vault_address(&program_id);
+    let (legacy_address, _) = find_legacy_stake_address(&program_id, &staker.pubkey());
+
+    // a position of the original program: 1_000 staked with 40 reward checkpointed,
+    // of which its shared vault only still holds 30
+    env.mint_to(&mint, &legacy_vault, 1_000 + 30).await;
+    let mut data = staker.pubkey().to_bytes().to_vec();
+    data.extend_from_slice(&1_000u64.to_le_bytes());
+    data.extend_from_slice(&40u64.to_le_bytes());
+    data.extend_from_slice(&env.now().await.to_le_bytes());
+    env.context.set_account(
+        &legacy_address,
+        &AccountSharedData::from(Account {
+            lamports: rent.minimum_balance(data.len()),
+            data,
+            owner: program_id,
+            executable: false,
+            rent_epoch: 0,
+        }),
+    );
+
+    let migrate =
+        instruction::migrate_legacy_stake_account(&program_id, POOL_ID, &mint, &staker.pubkey());
+    env.process(std::slice::from_ref(&migrate), &[&staker])
+        .await;
+
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 1_000);
+    assert_eq!(stake_data.remained_reward, 30);
+    assert_eq!(
+        stake_data.bump,
+        find_stake_address(&program_id, &pool, &staker.pubkey()).1
+    );
+    assert_eq!(env.pool_data().await.total_staked, 1_000);
+    assert_eq!(env.token_balance(&legacy_vault, &mint).await, 0);
+    assert_eq!(env.token_balance(&vault, &mint).await, 1_000);
+    assert_eq!(
+        env.token_balance(&reward_vault, &mint).await,
+        REWARD_FUNDING + 30
+    );
+    assert_eq!(
+        env.context
+            .banks_client
+            .get_account(legacy_address)
+            .await
+            .unwrap(),
+        None
+    );
+
+    // the legacy account is gone, so the position cannot be moved twice
+    env.context.get_new_latest_blockhash().await.unwrap();
+    assert_eq!(
+        env.try_process(&[migrate], &[&staker]).await,
+        Err(custom_error(MarketplaceError::StakeAccountNotInitialized))
+    );
+
+    // the moved position earns and withdraws like any other
+    env.warp(100).await;
+    assert_eq!(env.pending_reward().await, 30 + 100 * REWARD_RATE);
+    env.withdraw(1_000).await;
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &mint).await,
+        STAKER_BALANCE + 1_000
+    );
+}
+
+#[tokio::test]
+async fn test_stake_requires_generated_vault() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+    let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+
+    // a second pool whose vault PDA was never generated
+    env.process(
+        &[instruction::create_pool(
+            &program_id,
+            &admin,
+            1,
+            &stake_mint,
+            &reward_mint,
+            REWARD_RATE,
+        )],
+        &[],
+    )
+    .await;
+    let stake = instruction::stake(&program_id, 1, &stake_mint, &staker.pubkey(), 10, None);
+    assert_eq!(
+        env.try_process(&[stake], &[&staker]).await,
+        Err(custom_error(MarketplaceError::InvalidVaultAccount))
+    );
+}
+
+#[tokio::test]
+async fn test_create_pool_rejects_token_account_as_mint() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+    let token_account = get_associated_token_address(&env.staker.pubkey(), &stake_mint);
+
+    for (stake_mint, reward_mint) in [(token_account, reward_mint), (stake_mint, token_account)] {
+        let create_pool = instruction::create_pool(
+            &program_id,
+            &admin,
+            1,
+            &stake_mint,
+            &reward_mint,
+            REWARD_RATE,
+        );
+        assert_eq!(
+            env.try_process(&[create_pool], &[]).await,
+            Err(custom_error(MarketplaceError::InvalidMint))
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_stake_rejects_foreign_token_accounts() {
+    let mut env = Env::new().await;
+    let staker = env.staker.insecure_clone();
+    let admin = env.context.payer.pubkey();
+    let (stake_mint, reward_mint) = (env.stake_mint, env.reward_mint);
+    let stake = instruction::stake(
+        &env.program_id,
+        POOL_ID,
+        &stake_mint,
+        &staker.pubkey(),
+        1_000,
+        None,
+    );
+
+    // token accounts are checked by owner and mint, not by address
+    for (index, token_account, error) in [
+        (
+            5,
+            get_associated_token_address(&staker.pubkey(), &stake_mint),
+            MarketplaceError::InvalidVaultTokenAccount,
+        ),
+        (
+            6,
+            get_associated_token_address(&admin, &reward_mint),
+            MarketplaceError::InvalidUserTokenAccount,
+        ),
+    ] {
+        let mut ix = stake.clone();
+        ix.accounts[index].pubkey = token_account;
+        assert_eq!(
+            env.try_process(&[ix], &[&staker]).await,
+            Err(custom_error(error))
+        );
+    }
+
+    // a missing account is only accepted at the associated address it is created at
+    let mut ix = stake.clone();
+    ix.accounts[5].pubkey = Pubkey::new_unique();
+    assert_eq!(
+        env.try_process(&[ix], &[&staker]).await,
+        Err(custom_error(MarketplaceError::InvalidVaultTokenAccount))
+    );
+    env.stake(1_000).await;
+    let mut ix = instruction::claim(&env.program_id, POOL_ID, &reward_mint, &staker.pubkey());
+    ix.accounts[6].pubkey = Pubkey::new_unique();
+    assert_eq!(
+        env.try_process(&[ix], &[&staker]).await,
+        Err(custom_error(MarketplaceError::InvalidUserTokenAccount))
+    );
+}
+
+#[tokio::test]
+async fn test_withdraw_rejects_locked_stake() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let staker = env.staker.insecure_clone();
+    let withdraw =
+        instruction::withdraw(&program_id, POOL_ID, &env.stake_mint, &staker.pubkey(), 10);
+
+    env.set_lock_tier(15_000, 1_000).await;
+    env.stake_locked(1_000).await;
+    env.warp(LOCK_DURATION - 1).await;
+    assert_eq!(
+        env.try_process(&[withdraw], &[&staker]).await,
+        Err(custom_error(MarketplaceError::StakeLocked))
+    );
+
+    env.warp(1).await;
+    env.withdraw(1_000).await;
+    assert_eq!(env.stake_data().await.reward_multiplier_bps, BASIS_POINTS);
+}
+
+#[tokio::test]
+async fn test_early_withdraw_burns_penalty() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+
+    env.set_lock_tier(15_000, 1_000).await;
+    env.stake_locked(1_000).await;
+    let supply = env.mint_supply(&stake_mint).await;
+
+    env.process(
+        &[instruction::early_withdraw(
+            &program_id,
+            POOL_ID,
+            &stake_mint,
+            &staker.pubkey(),
+            600,
+        )],
+        &[&staker],
+    )
+    .await;
+    // 10% of the 600 withdrawn is burned
+    assert_eq!(env.mint_supply(&stake_mint).await, supply - 60);
+    assert_eq!(
+        env.token_balance(&staker.pubkey(), &stake_mint).await,
+        STAKER_BALANCE - 1_000 + 540
+    );
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.amount, 400);
+    assert_eq!(env.pool_data().await.total_staked, 400);
+}
+
+#[tokio::test]
+async fn test_release_expired_lock() {
+    let mut env = Env::new().await;
+    let program_id = env.program_id;
+    let admin = env.context.payer.pubkey();
+    let staker = env.staker.insecure_clone();
+    let stake_mint = env.stake_mint;
+    let release = instruction::release_expired_lock(&program_id, POOL_ID, &staker.pubkey());
+
+    // an unlocked stake of the same size, outweighed two to one by the lock
+    env.mint_to(&stake_mint, &admin, 1_000).await;
+    env.process(
+        &[instruction::stake(
+            &program_id,
+            POOL_ID,
+            &stake_mint,
+            &admin,
+            1_000,
+            None,
+        )],
+        &[],
+    )
+    .await;
+    env.set_lock_tier(20_000, 0).await;
+    env.stake_locked(1_000).await;
+    assert_eq!(env.pool_data().await.total_weight, 3_000);
+    assert_eq!(
+        env.try_process(std::slice::from_ref(&release), &[]).await,
+        Err(custom_error(MarketplaceError::StakeLocked))
+    );
+
+    // anyone may release it once over, without the staker's signature
+    env.warp(LOCK_DURATION).await;
+    env.context.get_new_latest_blockhash().await.unwrap();
+    env.process(std::slice::from_ref(&release), &[]).await;
+    let stake_data = env.stake_data().await;
+    assert_eq!(stake_data.lock_end, 0);
+    assert_eq!(stake_data.reward_multiplier_bps, BASIS_POINTS);
+    assert_eq!(env.pool_data().await.total_weight, 2_000);
+
+    // two thirds of the lock period, then half
+    env.warp(50).await;
+    assert_eq!(
+        env.pending_reward().await,
+        2 * LOCK_DURATION as u64 * REWARD_RATE / 3 + 50 * REWARD_RATE / 2
+    );
+
+    env.context.get_new_latest_blockhash().await.unwrap();
+    assert_eq!(
+        env.try_process(&[release], &[]).await,
+        Err(custom_error(MarketplaceError::NoExpiredLock))
+    );
 }
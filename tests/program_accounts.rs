@@ -0,0 +1,103 @@
+use {
+    marketplace::{error::MarketplaceError, instruction, process_instruction},
+    solana_program::{instruction::Instruction, pubkey::Pubkey},
+    solana_program_test::{processor, tokio, ProgramTest},
+    solana_sdk::{
+        instruction::InstructionError,
+        signature::Signer,
+        transaction::{Transaction, TransactionError},
+    },
+};
+
+// builds an instruction signed by the fee payer, swaps the account at `index`
+// for an impostor and expects `error`
+async fn assert_impostor_rejected<F>(build: F, index: usize, error: MarketplaceError)
+where
+    F: FnOnce(&Pubkey, &Pubkey) -> Instruction,
+{
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("marketplace", program_id, processor!(process_instruction));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut instruction = build(&program_id, &payer.pubkey());
+    instruction.accounts[index].pubkey = Pubkey::new_unique();
+
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+    transaction.sign(&[&payer], recent_blockhash);
+    let result = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        result,
+        TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+    );
+}
+
+#[tokio::test]
+async fn test_stake_rejects_impostor_token_program() {
+    assert_impostor_rejected(
+        |program_id, staker| {
+            instruction::stake(program_id, 0, &Pubkey::new_unique(), staker, 10, None)
+        },
+        7,
+        MarketplaceError::InvalidTokenProgram,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_withdraw_rejects_impostor_associated_token_program() {
+    assert_impostor_rejected(
+        |program_id, staker| {
+            instruction::withdraw(program_id, 0, &Pubkey::new_unique(), staker, 10)
+        },
+        8,
+        MarketplaceError::InvalidAssociatedTokenProgram,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_claim_rejects_impostor_system_program() {
+    assert_impostor_rejected(
+        |program_id, staker| instruction::claim(program_id, 0, &Pubkey::new_unique(), staker),
+        9,
+        MarketplaceError::InvalidSystemProgram,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_fund_rewards_rejects_impostor_rent_sysvar() {
+    assert_impostor_rejected(
+        |program_id, funder| {
+            instruction::fund_rewards(program_id, 0, &Pubkey::new_unique(), funder, 10)
+        },
+        9,
+        MarketplaceError::InvalidRentSysvar,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_initialize_rejects_impostor_system_program() {
+    assert_impostor_rejected(
+        instruction::initialize,
+        2,
+        MarketplaceError::InvalidSystemProgram,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_generate_vault_rejects_impostor_rent_sysvar() {
+    assert_impostor_rejected(
+        |program_id, admin| instruction::generate_vault(program_id, admin, 0),
+        5,
+        MarketplaceError::InvalidRentSysvar,
+    )
+    .await;
+}